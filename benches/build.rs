@@ -0,0 +1,70 @@
+//! How long it takes to build a site of a few thousand posts, using one
+//! thread and then all of them.
+
+use std::fs;
+use std::path::Path;
+
+use blake::config::Config;
+use criterion::{criterion_group, criterion_main, Criterion};
+
+/// How many posts the synthetic site has.
+const POSTS: usize = 2000;
+
+/// Write `count` posts, with headings, sidenotes, code and links, into
+/// `posts_dir`.
+fn write_posts(posts_dir: &Path, count: usize) {
+    fs::create_dir_all(posts_dir).unwrap();
+    for i in 0..count {
+        let name = format!(
+            "{}-{:02}-{:02}-post-{}.md",
+            2000 + i / 336,
+            i / 28 % 12 + 1,
+            i % 28 + 1,
+            i
+        );
+        let mut contents = format!("# Post number {}\n\n", i);
+        for paragraph in 0..10 {
+            contents.push_str(&format!(
+                "Paragraph {} has *emphasis*, `code`, a [link](/posts/{}.html) \
+                 and a note.[^{}] \"Quotes\" are made smart, and the rest is \
+                 filler text to give the post a realistic length.\n\n",
+                paragraph, paragraph, paragraph
+            ));
+        }
+        contents.push_str("```rust\nfn main() {\n    println!(\"hello\");\n}\n```\n\n");
+        for note in 0..10 {
+            contents.push_str(&format!("[^{}]: Sidenote {}.\n", note, note));
+        }
+        fs::write(posts_dir.join(name), contents).unwrap();
+    }
+}
+
+fn bench_build(c: &mut Criterion) {
+    let root = std::env::temp_dir().join("blake-bench-build");
+    let _ = fs::remove_dir_all(&root);
+    write_posts(&root.join("posts"), POSTS);
+    fs::create_dir_all(root.join("static")).unwrap();
+    let config = Config {
+        posts_dir: root.join("posts"),
+        pages_dir: root.join("pages"),
+        static_dir: root.join("static"),
+        output_dir: root.join("output"),
+        ..Config::default()
+    };
+
+    let mut group = c.benchmark_group("build");
+    group.sample_size(10);
+    group.bench_function("one thread", |b| {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        b.iter(|| pool.install(|| blake::build(&config).unwrap()))
+    });
+    group.bench_function("all threads", |b| b.iter(|| blake::build(&config).unwrap()));
+    group.finish();
+    fs::remove_dir_all(&root).unwrap();
+}
+
+criterion_group!(benches, bench_build);
+criterion_main!(benches);
@@ -1,12 +1,37 @@
-use fs_extra::dir;
-use std::error::Error;
+use std::collections::BTreeMap;
+use std::fmt;
 use std::path::{Path, PathBuf};
-use std::{ffi, fs, io};
+use std::time::{Duration, Instant};
+use std::{fs, io};
 
-use crate::posts;
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::theme::Theme;
+use crate::{pages, posts};
 
+mod assets;
+mod errors;
+mod figures;
+mod functions;
 mod html;
+mod images;
+mod manifest;
+mod permalink;
+mod relative;
+mod renderer;
 mod sidenotes;
+mod site;
+mod staging;
+mod static_files;
+pub mod stats;
+mod urls;
+
+use assets::Assets;
+use manifest::Manifest;
+use permalink::Permalink;
+pub use renderer::Renderer;
+pub use staging::Staging;
+use static_files::StaticFiles;
 
 pub struct OutputPath {
     pub path: PathBuf,
@@ -21,87 +46,479 @@ impl OutputPath {
         self.path.join("feed.xml")
     }
 
-    pub fn posts_dir(&self) -> PathBuf {
-        self.path.join("posts")
+    pub fn manifest(&self) -> PathBuf {
+        self.path.join(".blake-manifest.json")
+    }
+
+    pub fn netlify_redirects(&self) -> PathBuf {
+        self.path.join("_redirects")
+    }
+
+    pub fn nginx_redirects(&self) -> PathBuf {
+        self.path.join("redirects.nginx.conf")
+    }
+
+    /// The file that the page at the given URL is written to.
+    pub fn page(&self, url: &str) -> PathBuf {
+        self.path.join(permalink::url_to_path(url))
     }
 
     pub fn static_dir(&self) -> PathBuf {
         self.path.join("static")
     }
+
+    /// Which of the files every build writes is at the given URL, if any.
+    pub fn site_file(&self, url: &str) -> Option<&'static str> {
+        let path = self.page(url);
+        let site_files = [
+            (self.index(), "the index"),
+            (self.feed(), "the feed"),
+            (self.manifest(), "the manifest"),
+            (self.netlify_redirects(), "the redirect map"),
+            (self.nginx_redirects(), "the redirect map"),
+        ];
+        site_files
+            .iter()
+            .find(|(file, _)| *file == path)
+            .map(|(_, name)| *name)
+    }
+}
+
+/// What a build did to the site's posts.
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// Posts whose pages were new or changed.
+    pub rendered: usize,
+    /// Posts whose pages were already up to date.
+    pub unchanged: usize,
+    /// Pages from the previous build that aren't in this one.
+    pub removed: usize,
+    /// How long the build took.
+    pub elapsed: Duration,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} rendered, {} unchanged, {} removed in {:.2}s",
+            self.rendered,
+            if self.rendered == 1 { "post" } else { "posts" },
+            self.unchanged,
+            self.removed,
+            self.elapsed.as_secs_f64()
+        )
+    }
 }
 
 pub fn build(
-    static_dir: &Path,
+    config: &Config,
+    theme: &Theme,
+    renderer: &mut Renderer,
     posts: &posts::Posts,
+    pages: &pages::Pages,
     output: &OutputPath,
-) -> Result<(), Box<dyn Error>> {
-    copy_static_resources(static_dir, &output.static_dir())?;
+) -> Result<Summary> {
+    let mut summary = Summary::default();
+    let mut static_files = StaticFiles::collect(theme, &config.static_dir, &config.assets.ignore)?;
+    let mut assets = Assets::new(&static_files);
+    if config.assets.fingerprint {
+        assets = Assets::fingerprint(&mut static_files, config.assets.keep_unhashed)?;
+        renderer.set_assets(assets.clone());
+    }
+    static_files.sync(&output.static_dir())?;
 
-    let mut html_posts = build_posts(posts, &output.posts_dir())?;
+    let previous = match Manifest::load(&output.manifest()) {
+        Ok(Some(previous)) => previous,
+        Ok(None) => {
+            let posts_dir = output.path.join("posts");
+            Manifest::scan_old_build(&posts_dir).map_err(|e| Error::Io(posts_dir, e))?
+        }
+        Err(e) => return Err(Error::Io(output.manifest(), e)),
+    };
+    let mut manifest = Manifest::default();
+    let mut html_posts = build_posts(config, posts, &mut assets, &mut manifest)?;
     html_posts.sort_by_key(|post| *post.date());
     html_posts.reverse();
+    let html_pages = build_pages(config, pages, &assets, &mut manifest)?;
+    add_aliases(&mut manifest, &html_posts, output)?;
+    renderer
+        .set_posts(&html_posts)
+        .map_err(|e| Error::Template(config.posts_dir.clone(), e))?;
+    renderer.set_pages(&html_pages);
+    summary.rendered = write_posts(renderer, &html_posts, output, config.fail_fast)?;
+    summary.unchanged = html_posts.len() - summary.rendered;
+    write_pages(renderer, &html_pages, output)?;
+    write_images(&html_posts, &html_pages, output, config.fail_fast)?;
+    manifest.carry_over_redirects(&previous);
 
-    html::write_index_html(&html_posts, &output.index())?;
-    generate_feed(&output.feed());
-    remove_deleted_posts(posts, &output.posts_dir())?;
-    Ok(())
+    html::write_index_html(renderer, &html_posts, config, &output.index())?;
+    html::write_feed(renderer, &html_posts, config, &output.feed())?;
+    summary.removed = remove_deleted_pages(&previous, &manifest, output)?;
+    write_redirects(renderer, config, &manifest, output)?;
+    static_files::remove_file(&output.manifest())?;
+    manifest
+        .save(&output.manifest())
+        .map_err(|e| Error::Io(output.manifest(), e))?;
+    Ok(summary)
+}
+
+/// Gather statistics about all the posts.
+pub fn stats(config: &Config, theme: &Theme, posts: &posts::Posts) -> Result<stats::Stats> {
+    let static_files = StaticFiles::collect(theme, &config.static_dir, &config.assets.ignore)?;
+    let mut assets = Assets::new(&static_files);
+    let mut html_posts = Vec::new();
+    for (post, permalink) in open_posts(config, posts, &mut assets)? {
+        html_posts.push(html::Post::render(&post, &permalink, config, &assets)?);
+    }
+    Ok(stats::Stats::new(&html_posts))
+}
+
+/// Render all the posts, adding them to the manifest, and their own files to
+/// `assets`.
+///
+/// Posts are rendered in parallel. If any of them fail, the error lists all
+/// the posts that did, unless `config.fail_fast` says to stop at the first.
+fn build_posts(
+    config: &Config,
+    posts: &posts::Posts,
+    assets: &mut Assets,
+    manifest: &mut Manifest,
+) -> Result<Vec<html::Post>> {
+    let start = Instant::now();
+    let opened = open_posts(config, posts, assets)?;
+    let assets = &*assets;
+    let html_posts = errors::try_each(&opened, config.fail_fast, |_, (post, permalink)| {
+        html::Post::render(post, permalink, config, assets)
+    })?;
+    log::debug!(
+        "Rendered {} posts in {:.2}s",
+        html_posts.len(),
+        start.elapsed().as_secs_f64()
+    );
+    let added = html_posts
+        .iter()
+        .map(|html_post| add_post(manifest, html_post))
+        .collect();
+    errors::all_or_errors(added)?;
+    Ok(html_posts)
+}
+
+/// Open all the posts and work out their permalinks, adding their own files
+/// to `assets`, so that any post can show another's images.
+fn open_posts(
+    config: &Config,
+    posts: &posts::Posts,
+    assets: &mut Assets,
+) -> Result<Vec<(posts::Post, Permalink)>> {
+    let paths = post_paths(config, posts)?;
+    let opened = errors::try_each(&paths, config.fail_fast, |_, path| {
+        open_post(config, posts, path)
+    })?;
+    for (post, permalink) in &opened {
+        for file in html::bundle_files(post, permalink)? {
+            assets.add_file(&file.url, &file.source);
+        }
+    }
+    Ok(opened)
 }
 
-// TODO: Add logging.
-
-fn copy_static_resources(
-    input_dir: &Path,
-    output_dir: &Path,
-) -> Result<u64, fs_extra::error::Error> {
-    // Delete the existing static directory.
-    match fs::remove_dir_all(output_dir) {
-        Ok(_) => {}
-        Err(err) => match err.kind() {
-            io::ErrorKind::NotFound => {}
-            _ => panic!(
-                "Could not remove directory: {}: {}",
-                output_dir.display(),
-                err
+/// The paths of all the posts, in order.
+fn post_paths(config: &Config, posts: &posts::Posts) -> Result<Vec<PathBuf>> {
+    let paths = posts
+        .post_paths()
+        .map_err(|e| Error::Io(config.posts_dir.clone(), e))?;
+    log::debug!(
+        "Found {} posts in {}",
+        paths.len(),
+        config.posts_dir.display()
+    );
+    Ok(paths)
+}
+
+/// The post at `path`, and where it's published.
+fn open_post(
+    config: &Config,
+    posts: &posts::Posts,
+    path: &Path,
+) -> Result<(posts::Post, Permalink)> {
+    let post = posts
+        .post(path.to_owned())
+        .map_err(|e| Error::post(path, e))?;
+    let permalink = Permalink::new(&config.permalink, &post).map_err(|e| Error::Source {
+        path: path.to_owned(),
+        line: None,
+        message: e.to_string(),
+    })?;
+    Ok((post, permalink))
+}
+
+/// Add a post's page, redirects and files to the manifest.
+fn add_post(manifest: &mut Manifest, html_post: &html::Post) -> Result<()> {
+    if let Some(other) = manifest.add_post(html_post.name(), html_post.url()) {
+        return Err(Error::Source {
+            path: html_post.path().to_owned(),
+            line: None,
+            message: format!(
+                "Post has the same permalink as {}: {}",
+                other,
+                html_post.url()
             ),
-        },
+        });
     }
-    // Copy the one in the library.
-    let mut options = dir::CopyOptions::new();
-    options.copy_inside = true;
-    dir::copy(input_dir, output_dir, &options)
+    for asset in html_post.assets() {
+        if let Some(other) = manifest.add_asset(&asset.url, html_post.name()) {
+            return Err(Error::Source {
+                path: asset.source.clone(),
+                line: None,
+                message: format!("File has the same URL as {}: {}", other, asset.url),
+            });
+        }
+    }
+    for image in html_post.images() {
+        if let Some(other) = manifest.add_image(&image.url, html_post.name()) {
+            return Err(Error::Source {
+                path: html_post.path().to_owned(),
+                line: None,
+                message: format!("Image has the same URL as {}: {}", other, image.url),
+            });
+        }
+    }
+    Ok(())
 }
 
-fn build_posts(posts: &posts::Posts, output_dir: &Path) -> Result<Vec<html::Post>, Box<dyn Error>> {
-    fs::create_dir_all(output_dir)?;
-    let posts = posts.iter_posts()?;
-    let mut html_posts = Vec::new();
-    for post in posts {
-        let post = post?;
-        let html_post = html::Post::render(&post)?;
-        let html_path = output_dir.join(post.name()).with_extension("html");
-        html_post.write_html(&html_path)?;
-        html_posts.push(html_post);
+/// Add every post's aliases to the manifest.
+///
+/// Must be called after all the posts and pages have been added to the
+/// manifest, so that no alias can replace one of them.
+fn add_aliases(
+    manifest: &mut Manifest,
+    html_posts: &[html::Post],
+    output: &OutputPath,
+) -> Result<()> {
+    let added = html_posts
+        .iter()
+        .map(|html_post| add_post_aliases(manifest, html_post, output))
+        .collect();
+    errors::all_or_errors(added)?;
+    Ok(())
+}
+
+fn add_post_aliases(
+    manifest: &mut Manifest,
+    html_post: &html::Post,
+    output: &OutputPath,
+) -> Result<()> {
+    for alias in html_post.aliases() {
+        let problem = if !alias.starts_with('/') {
+            Some(String::from("Alias must start with '/'"))
+        } else if alias.split('/').any(|part| part == "." || part == "..") {
+            Some(String::from("Alias cannot contain '.' or '..'"))
+        } else if let Some(file) = output.site_file(alias) {
+            Some(format!("Alias would replace {}", file))
+        } else {
+            manifest
+                .add_alias(alias, html_post.name())
+                .map(|other| format!("Alias is already the URL of {}", other))
+        };
+        if let Some(problem) = problem {
+            return Err(Error::Source {
+                path: html_post.path().to_owned(),
+                line: None,
+                message: format!("{}: {}", problem, alias),
+            });
+        }
     }
-    Ok(html_posts)
+    Ok(())
 }
 
-fn remove_deleted_posts(posts: &posts::Posts, output_dir: &Path) -> io::Result<()> {
-    let html_posts = fs::read_dir(output_dir)?
-        .filter_map(|e| e.ok())
-        .map(|e| e.path())
-        .filter(|path| path.extension() == Some(ffi::OsStr::new("html")));
-    for html_path in html_posts {
-        let name = html_path
-            .file_stem()
-            .expect("Files from a directory listing should have file names");
-        if !posts.is_post(name) {
-            fs::remove_file(html_path)?;
+/// Render all the standalone pages, adding them to the manifest.
+///
+/// Must be called after all the posts have been added to the manifest.
+fn build_pages(
+    config: &Config,
+    pages: &pages::Pages,
+    assets: &Assets,
+    manifest: &mut Manifest,
+) -> Result<Vec<html::Page>> {
+    let mut html_pages = Vec::new();
+    let pages = pages
+        .pages()
+        .map_err(|e| Error::Io(config.pages_dir.clone(), e))?;
+    for page in pages {
+        let source = page
+            .path()
+            .strip_prefix(&config.pages_dir)
+            .unwrap_or_else(|_| page.path())
+            .display()
+            .to_string();
+        if page.url() == "/" {
+            return Err(Error::Source {
+                path: page.path().to_owned(),
+                line: None,
+                message: String::from("Page would replace the index"),
+            });
+        }
+        if let Some(other) = manifest.add_standalone(&source, page.url()) {
+            return Err(Error::Source {
+                path: page.path().to_owned(),
+                line: None,
+                message: format!("Page has the same URL as {}: {}", other, page.url()),
+            });
         }
+        let html_page = html::Page::render(&page, config, assets)?;
+        for image in html_page.images() {
+            if let Some(other) = manifest.add_image(&image.url, &source) {
+                return Err(Error::Source {
+                    path: page.path().to_owned(),
+                    line: None,
+                    message: format!("Image has the same URL as {}: {}", other, image.url),
+                });
+            }
+        }
+        html_pages.push(html_page);
+    }
+    Ok(html_pages)
+}
+
+/// Write a page for each post, given all the posts, newest first.
+///
+/// The posts' pages are written, and their files copied, in parallel.
+/// Returns how many of the pages changed.
+fn write_posts(
+    renderer: &Renderer,
+    html_posts: &[html::Post],
+    output: &OutputPath,
+    fail_fast: bool,
+) -> Result<usize> {
+    let start = Instant::now();
+    let changed = errors::try_each(html_posts, fail_fast, |i, html_post| {
+        let newer = i.checked_sub(1).map(|newer| &html_posts[newer]);
+        let older = html_posts.get(i + 1);
+        write_post(renderer, html_post, older, newer, output)
+    })?;
+    log::debug!(
+        "Wrote {} posts in {:.2}s",
+        html_posts.len(),
+        start.elapsed().as_secs_f64()
+    );
+    Ok(changed.into_iter().filter(|changed| *changed).count())
+}
+
+fn write_post(
+    renderer: &Renderer,
+    html_post: &html::Post,
+    older: Option<&html::Post>,
+    newer: Option<&html::Post>,
+    output: &OutputPath,
+) -> Result<bool> {
+    let html_path = output.page(html_post.url());
+    create_parent_dir(&html_path)?;
+    let changed = html_post.write_html(renderer, older, newer, &html_path)?;
+    for asset in html_post.assets() {
+        let dest = output.page(&asset.url);
+        create_parent_dir(&dest)?;
+        static_files::copy_if_changed(&asset.source, &dest)?;
     }
+    Ok(changed)
+}
+
+/// Make the directory that `path` goes in, if it isn't there already.
+fn create_parent_dir(path: &Path) -> Result<()> {
+    match path.parent() {
+        Some(parent) => fs::create_dir_all(parent).map_err(|e| Error::Io(parent.to_owned(), e)),
+        None => Ok(()),
+    }
+}
+
+fn write_pages(renderer: &Renderer, html_pages: &[html::Page], output: &OutputPath) -> Result<()> {
+    for html_page in html_pages {
+        let html_path = output.page(html_page.url());
+        create_parent_dir(&html_path)?;
+        html_page.write_html(renderer, &html_path)?;
+    }
+    Ok(())
+}
+
+/// Make the smaller copies of the images in posts and pages, in parallel.
+///
+/// Each copy is only made once, however many posts show the image.
+fn write_images(
+    html_posts: &[html::Post],
+    html_pages: &[html::Page],
+    output: &OutputPath,
+    fail_fast: bool,
+) -> Result<()> {
+    let images = html_posts
+        .iter()
+        .flat_map(|post| post.images())
+        .chain(html_pages.iter().flat_map(|page| page.images()))
+        .map(|image| (image.url.as_str(), image))
+        .collect::<BTreeMap<_, _>>();
+    let images = images.into_iter().collect::<Vec<_>>();
+    errors::try_each(&images, fail_fast, |_, (url, image)| {
+        image.write(&output.page(url))
+    })?;
     Ok(())
 }
 
-fn generate_feed(feed_page: &Path) {
-    // TODO: implement generate_feed. Ideally use third-party library.
-    println!("generate_feed({})", feed_page.display());
+/// Remove the pages that were in the previous build, but aren't in this one,
+/// along with any directories that leaves empty.
+///
+/// Returns how many pages were removed.
+fn remove_deleted_pages(
+    previous: &Manifest,
+    current: &Manifest,
+    output: &OutputPath,
+) -> Result<usize> {
+    let mut removed = 0;
+    for url in previous.removed_pages(current) {
+        let html_path = output.page(url);
+        match fs::remove_file(&html_path) {
+            Ok(_) => {
+                log::debug!("Removed {}", url);
+                removed += 1;
+            }
+            Err(err) => match err.kind() {
+                io::ErrorKind::NotFound => {}
+                _ => return Err(Error::Io(html_path, err)),
+            },
+        }
+        let mut dir = html_path.parent();
+        while let Some(path) = dir {
+            if path == output.path || fs::remove_dir(path).is_err() {
+                break;
+            }
+            dir = path.parent();
+        }
+    }
+    Ok(removed)
+}
+
+/// Write a page for each redirect, and any configured redirect maps.
+fn write_redirects(
+    renderer: &Renderer,
+    config: &Config,
+    manifest: &Manifest,
+    output: &OutputPath,
+) -> Result<()> {
+    let mut netlify = String::new();
+    let mut nginx = String::from("map $request_uri $blake_redirect {\n");
+    for (from, to) in manifest.redirects() {
+        let html_path = output.page(from);
+        create_parent_dir(&html_path)?;
+        html::write_redirect(renderer, from, to, &html_path)?;
+        let urls = renderer.urls();
+        let (from, to) = (urls.path(from), urls.path(to));
+        netlify.push_str(&format!("{} {} 301\n", from, to));
+        nginx.push_str(&format!("    \"{}\" \"{}\";\n", from, to));
+    }
+    nginx.push_str("}\n");
+    if config.redirects.netlify {
+        static_files::write_if_changed(netlify.as_bytes(), &output.netlify_redirects())?;
+    }
+    if config.redirects.nginx {
+        static_files::write_if_changed(nginx.as_bytes(), &output.nginx_redirects())?;
+    }
+    Ok(())
 }
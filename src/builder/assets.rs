@@ -0,0 +1,285 @@
+//! The files a site publishes besides its pages: where each comes from, and
+//! names for static files that change when their contents do, so browsers
+//! and CDNs can cache them forever without serving stale copies.
+
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use super::static_files::StaticFiles;
+use super::urls;
+use crate::error::{Error, Result};
+
+/// Where static files are published.
+const STATIC_URL: &str = "/static/";
+
+/// The file in the static directory that maps each file to its hashed name,
+/// for anything outside blake that needs to find them.
+const MANIFEST_FILE: &str = "assets.json";
+
+/// How many hex digits of the hash go in a file's name.
+const HASH_LENGTH: usize = 8;
+
+/// The static files and posts' own files, and the hashed names of the
+/// static files.
+#[derive(Clone, Debug, Default)]
+pub struct Assets {
+    /// Paths within the static directory, and what they're called now.
+    names: BTreeMap<String, String>,
+    /// Where each file comes from, by the URL it had before it was hashed.
+    sources: BTreeMap<String, Source>,
+}
+
+/// Where a file that the site publishes comes from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    File(PathBuf),
+    /// Contents that blake has itself, like the files of a built-in theme.
+    Builtin,
+}
+
+impl Assets {
+    /// The static files, by their own names: the site's, and then the theme's.
+    pub fn new(files: &StaticFiles) -> Assets {
+        let sources = files
+            .paths()
+            .into_iter()
+            .map(|path| {
+                let source = files
+                    .source(&path)
+                    .map_or(Source::Builtin, |source| Source::File(source.to_owned()));
+                (format!("{}{}", STATIC_URL, path), source)
+            })
+            .collect();
+        Assets {
+            names: BTreeMap::new(),
+            sources,
+        }
+    }
+
+    /// Publish every static file under a name with a hash of its contents,
+    /// so `tufte.css` becomes something like `tufte.3f2a9c1b.css`, and add a
+    /// manifest of the new names.
+    ///
+    /// Stylesheets are hashed last, after their `url()`s are changed to the
+    /// new names of the fonts and images they use. With `keep_unhashed`, the
+    /// files are published under their own names too.
+    pub fn fingerprint(files: &mut StaticFiles, keep_unhashed: bool) -> Result<Assets> {
+        if files.contains(MANIFEST_FILE) {
+            return Err(Error::Asset(
+                PathBuf::from(MANIFEST_FILE),
+                String::from(
+                    "Static file has the name of the list of fingerprinted files. \
+                     Rename it, or turn off assets.fingerprint",
+                ),
+            ));
+        }
+        let (stylesheets, others): (Vec<_>, Vec<_>) = files
+            .paths()
+            .into_iter()
+            .partition(|path| path.ends_with(".css"));
+        let mut assets = Assets::new(files);
+        for path in others.into_iter().chain(stylesheets) {
+            if path.ends_with(".css") {
+                let css = String::from_utf8_lossy(&files.read(&path)?).into_owned();
+                let rewritten = rewrite_css_urls(&css, &path, &assets.names);
+                if rewritten != css {
+                    files.add(&path, rewritten.into_bytes());
+                }
+            }
+            let hashed = hashed_name(&path, &files.read(&path)?);
+            if keep_unhashed {
+                files.copy(&path, &hashed);
+            } else {
+                files.rename(&path, &hashed);
+            }
+            assets.names.insert(path, hashed);
+        }
+        let manifest = serde_json::to_vec_pretty(&assets.names)
+            .map_err(|e| Error::Asset(PathBuf::from(MANIFEST_FILE), e.to_string()))?;
+        files.add(MANIFEST_FILE, manifest);
+        Ok(assets)
+    }
+
+    /// Record that one of a post's own files is published at `url`.
+    ///
+    /// Static files keep any URL they share with a post's file.
+    pub fn add_file(&mut self, url: &str, source: &Path) {
+        self.sources
+            .entry(url.to_owned())
+            .or_insert_with(|| Source::File(source.to_owned()));
+    }
+
+    /// Where the file published at `url` comes from, if there is one.
+    pub fn source(&self, url: &str) -> Option<&Source> {
+        self.sources.get(url)
+    }
+
+    /// The URL to use for the page at `url`: the hashed name, if it's one of
+    /// the static files, or `url` itself if not.
+    pub fn url(&self, url: &str) -> String {
+        url.strip_prefix(STATIC_URL)
+            .and_then(|path| self.names.get(path))
+            .map_or_else(|| url.to_owned(), |name| format!("{}{}", STATIC_URL, name))
+    }
+}
+
+/// The name of the file at `path` with a hash of `contents` before its
+/// extension.
+fn hashed_name(path: &str, contents: &[u8]) -> String {
+    let hash = Sha256::digest(contents)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    let hash = &hash[..HASH_LENGTH];
+    let file_start = path.rfind('/').map_or(0, |i| i + 1);
+    match path[file_start..].rfind('.') {
+        Some(dot) if dot > 0 => {
+            let (stem, extension) = path.split_at(file_start + dot);
+            format!("{}.{}{}", stem, hash, extension)
+        }
+        _ => format!("{}.{}", path, hash),
+    }
+}
+
+/// Change the `url()`s in the stylesheet at `path` that point to files in
+/// `names` to point to their new names instead.
+///
+/// URLs can be relative to the stylesheet or start with `/static/`, and keep
+/// any query or fragment. Anything else, like `data:` URLs, is left alone.
+fn rewrite_css_urls(css: &str, path: &str, names: &BTreeMap<String, String>) -> String {
+    let dir = path.rfind('/').map_or("", |i| &path[..=i]);
+    let mut rewritten = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(start) = rest.find("url(") {
+        let (before, after) = rest.split_at(start + "url(".len());
+        rewritten.push_str(before);
+        let quote = after
+            .trim_start()
+            .chars()
+            .next()
+            .filter(|c| *c == '"' || *c == '\'');
+        let url_start = after.len() - after.trim_start().len() + quote.map_or(0, char::len_utf8);
+        let url_end = match quote {
+            Some(quote) => after[url_start..].find(quote),
+            None => after[url_start..].find(|c: char| c == ')' || c.is_whitespace()),
+        }
+        .map_or(after.len(), |end| url_start + end);
+        let url = &after[url_start..url_end];
+        let end = url.find(['?', '#']).unwrap_or(url.len());
+        let (file, suffix) = url.split_at(end);
+        rewritten.push_str(&after[..url_start]);
+        match resolve_css_url(dir, file).and_then(|file| names.get(&file)) {
+            Some(hashed) => {
+                let keep = file.rfind('/').map_or(0, |i| i + 1);
+                let hashed_file = hashed.rfind('/').map_or(0, |i| i + 1);
+                rewritten.push_str(&file[..keep]);
+                rewritten.push_str(&hashed[hashed_file..]);
+                rewritten.push_str(suffix);
+            }
+            None => rewritten.push_str(url),
+        }
+        rest = &after[url_end..];
+    }
+    rewritten.push_str(rest);
+    rewritten
+}
+
+/// The path within the static directory of `url`, from a stylesheet in
+/// `dir`, if it's a static file.
+fn resolve_css_url(dir: &str, url: &str) -> Option<String> {
+    if url.is_empty() || url.contains(':') || url.starts_with("//") {
+        return None;
+    }
+    let url = if url.starts_with('/') {
+        url.to_owned()
+    } else {
+        urls::resolve(&format!("{}{}", STATIC_URL, dir), url)
+    };
+    url.strip_prefix(STATIC_URL).map(str::to_owned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hashed_name() {
+        assert_eq!(hashed_name("tufte.css", b"body {}"), "tufte.62368a1a.css");
+        assert_eq!(
+            hashed_name("fonts/et-book.v2/roman", b""),
+            "fonts/et-book.v2/roman.e3b0c442"
+        );
+        assert_eq!(hashed_name(".htaccess", b""), ".htaccess.e3b0c442");
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let mut files = StaticFiles::default();
+        files.add("tufte.css", b"body {}".to_vec());
+        files.add("js/site.js", Vec::new());
+        let assets = Assets::fingerprint(&mut files, false).unwrap();
+        let manifest = files.read(MANIFEST_FILE).unwrap().into_owned();
+
+        assert_eq!(
+            files.paths(),
+            [MANIFEST_FILE, "js/site.e3b0c442.js", "tufte.62368a1a.css"]
+        );
+        assert_eq!(
+            assets.url("/static/tufte.css"),
+            "/static/tufte.62368a1a.css"
+        );
+        assert_eq!(
+            assets.url("/static/js/site.js"),
+            "/static/js/site.e3b0c442.js"
+        );
+        assert_eq!(assets.url("/static/missing.css"), "/static/missing.css");
+        assert_eq!(assets.url("/tufte.css"), "/tufte.css");
+        assert!(String::from_utf8(manifest)
+            .unwrap()
+            .contains("\"tufte.css\": \"tufte.62368a1a.css\""));
+    }
+
+    #[test]
+    fn test_fingerprint_stylesheets() {
+        let css = "@font-face { src: url(\"../fonts/roman.woff\") format(\"woff\"), \
+                   url('../fonts/roman.eot?#iefix'); }\n\
+                   body { background: url( /static/bg.png ); }\n\
+                   a { background: url(data:image/png;base64,AAAA) url(missing.png); }\n";
+        let mut files = StaticFiles::default();
+        files.add("css/site.css", css.as_bytes().to_vec());
+        files.add("fonts/roman.woff", b"woff".to_vec());
+        files.add("fonts/roman.eot", b"eot".to_vec());
+        files.add("bg.png", b"png".to_vec());
+        let assets = Assets::fingerprint(&mut files, false).unwrap();
+        let url = |path: &str| assets.url(&format!("/static/{}", path));
+        let site_css = url("css/site.css");
+        let rewritten = files.read(&site_css["/static/".len()..]).unwrap();
+        let rewritten = String::from_utf8_lossy(&rewritten);
+        let name = |path: &str| url(path).rsplit('/').next().unwrap().to_owned();
+
+        assert_eq!(
+            rewritten,
+            format!(
+                "@font-face {{ src: url(\"../fonts/{}\") format(\"woff\"), \
+                 url('../fonts/{}?#iefix'); }}\n\
+                 body {{ background: url( /static/{} ); }}\n\
+                 a {{ background: url(data:image/png;base64,AAAA) url(missing.png); }}\n",
+                name("fonts/roman.woff"),
+                name("fonts/roman.eot"),
+                name("bg.png")
+            )
+        );
+        assert_ne!(
+            site_css,
+            format!("/static/{}", hashed_name("css/site.css", css.as_bytes()))
+        );
+    }
+
+    #[test]
+    fn test_fingerprint_manifest_name_taken() {
+        let mut files = StaticFiles::default();
+        files.add(MANIFEST_FILE, Vec::new());
+        assert!(Assets::fingerprint(&mut files, false).is_err());
+    }
+}
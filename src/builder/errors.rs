@@ -0,0 +1,103 @@
+//! Collecting what went wrong with each file, so that one bad post doesn't
+//! hide the problems with the rest.
+
+use rayon::prelude::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::error::{Error, Result};
+
+/// Check the results of doing something to many files.
+///
+/// Returns all the values if nothing went wrong, the error if one thing did,
+/// and all the errors if more than one did.
+pub fn all_or_errors<T>(results: Vec<Result<T>>) -> Result<Vec<T>> {
+    let mut values = Vec::with_capacity(results.len());
+    let mut errors = Vec::new();
+    for result in results {
+        match result {
+            Ok(value) => values.push(value),
+            Err(Error::Files(more)) => errors.extend(more),
+            Err(error) => errors.push(error),
+        }
+    }
+    match errors.len() {
+        0 => Ok(values),
+        1 => Err(errors.remove(0)),
+        _ => Err(Error::Files(errors)),
+    }
+}
+
+/// Do `f` to each of `items` in parallel, returning the results in the same
+/// order as the items.
+///
+/// If any fail, either stop at the first error, with `fail_fast`, or keep
+/// going and return all of them.
+///
+/// The first error is the first in the order of the items, whichever fails
+/// first: items after one that has failed are skipped, but those before it
+/// are all tried.
+pub fn try_each<I, T, F>(items: &[I], fail_fast: bool, f: F) -> Result<Vec<T>>
+where
+    I: Sync,
+    T: Send,
+    F: Fn(usize, &I) -> Result<T> + Sync + Send,
+{
+    if !fail_fast {
+        let results = items.par_iter().enumerate().map(|(i, item)| f(i, item));
+        return all_or_errors(results.collect());
+    }
+    let first_error = AtomicUsize::new(usize::MAX);
+    let results: Vec<Option<Result<T>>> = items
+        .par_iter()
+        .enumerate()
+        .map(|(i, item)| {
+            if i > first_error.load(Ordering::Relaxed) {
+                return None;
+            }
+            let result = f(i, item);
+            if result.is_err() {
+                first_error.fetch_min(i, Ordering::Relaxed);
+            }
+            Some(result)
+        })
+        .collect();
+    results.into_iter().flatten().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_try_each() {
+        let check = |_, n: &i32| {
+            if n % 2 == 0 {
+                Ok(n * 10)
+            } else {
+                Err(Error::Markdown(
+                    PathBuf::from(format!("{}.md", n)),
+                    String::from("odd"),
+                ))
+            }
+        };
+        assert_eq!(try_each(&[2, 4], false, check).unwrap(), [20, 40]);
+        let one = try_each(&[1, 2], false, check).unwrap_err();
+        let all = try_each(&[1, 2, 3], false, check).unwrap_err();
+        let first = try_each(&[1, 2, 3], true, check).unwrap_err();
+
+        assert_eq!(one.to_string(), "1.md: odd");
+        assert_eq!(
+            all.to_string(),
+            "2 files could not be built:\n  1.md: odd\n  3.md: odd"
+        );
+        assert_eq!(first.to_string(), "1.md: odd");
+        let many = (0..1000)
+            .map(|i| if i < 700 { i * 2 } else { i * 2 + 1 })
+            .collect::<Vec<_>>();
+        for _ in 0..20 {
+            let first = try_each(&many, true, check).unwrap_err();
+            assert_eq!(first.to_string(), "1401.md: odd");
+        }
+    }
+}
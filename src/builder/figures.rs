@@ -0,0 +1,113 @@
+//! Images with captions, shown as Tufte CSS figures.
+//!
+//! An image on its own in a paragraph becomes a figure if it has a title,
+//! which is used as the caption, or an attribute block after it:
+//!
+//! ```markdown
+//! ![A plot](plot.png "What the plot shows")
+//!
+//! ![A wide plot](wide.png "Spans the page"){.fullwidth}
+//!
+//! ![A small plot](small.png "Sits beside the text"){.margin}
+//! ```
+//!
+//! Margin figures are margin notes, so they fold away behind the same
+//! checkbox toggle as sidenotes on narrow screens.
+
+use comrak::nodes::{AstNode, NodeHtmlBlock, NodeValue};
+
+use super::sidenotes;
+
+/// How a figure is laid out.
+#[derive(Debug, PartialEq)]
+enum Layout {
+    /// In the main column, with the caption in the margin.
+    Normal,
+    /// Across the whole page, including the margin.
+    FullWidth,
+    /// In the margin, beside the text.
+    Margin,
+}
+
+/// An image that is shown as a figure, replacing the paragraph it's in.
+#[derive(Debug)]
+pub struct Figure<'a> {
+    image: &'a AstNode<'a>,
+    paragraph: &'a AstNode<'a>,
+    layout: Layout,
+    caption: String,
+}
+
+impl<'a> Figure<'a> {
+    /// The figure that `image`, whose title is `caption`, is shown as, if any.
+    ///
+    /// Fails if the image has an attribute block we don't understand.
+    pub fn find(image: &'a AstNode<'a>, caption: &str) -> Result<Option<Figure<'a>>, String> {
+        let paragraph = match image.parent() {
+            Some(parent) if matches!(parent.data.borrow().value, NodeValue::Paragraph) => parent,
+            _ => return Ok(None),
+        };
+        if image.previous_sibling().is_some() {
+            return Ok(None);
+        }
+        let mut attributes = String::new();
+        for sibling in image.following_siblings().skip(1) {
+            match &sibling.data.borrow().value {
+                NodeValue::Text(text) => attributes.push_str(&String::from_utf8_lossy(text)),
+                _ => return Ok(None),
+            }
+        }
+        let layout = match attributes.trim() {
+            "" if caption.is_empty() => return Ok(None),
+            "" => Layout::Normal,
+            "{.fullwidth}" => Layout::FullWidth,
+            "{.margin}" => Layout::Margin,
+            other if other.starts_with('{') && other.ends_with('}') => {
+                return Err(format!("Unknown figure attributes: {}", other))
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(Figure {
+            image,
+            paragraph,
+            layout,
+            caption: caption.to_owned(),
+        }))
+    }
+
+    /// Replace the figure's paragraph with the figure, showing `img_html`.
+    ///
+    /// `id` identifies a margin figure's toggle, and must be unique in the page.
+    pub fn render(self, img_html: &str, id: &str) {
+        let caption = tera::escape_html(&self.caption);
+        if self.layout == Layout::Margin {
+            // Margin notes go inline, in the paragraph where the image was.
+            let note = format!("{}{}", img_html, caption);
+            let html = sidenotes::render_margin_note_html(id, &note);
+            let siblings = self.image.following_siblings().skip(1).collect::<Vec<_>>();
+            for sibling in siblings {
+                sibling.detach();
+            }
+            self.image.data.borrow_mut().value = NodeValue::HtmlInline(html.into_bytes());
+            return;
+        }
+        let class = match self.layout {
+            Layout::FullWidth => " class=\"fullwidth\"",
+            _ => "",
+        };
+        let caption = if caption.is_empty() {
+            String::new()
+        } else {
+            format!("<figcaption>{}</figcaption>", caption)
+        };
+        let html = format!("<figure{}>{}{}</figure>\n", class, img_html, caption);
+        let children = self.paragraph.children().collect::<Vec<_>>();
+        for child in children {
+            child.detach();
+        }
+        self.paragraph.data.borrow_mut().value = NodeValue::HtmlBlock(NodeHtmlBlock {
+            block_type: 0,
+            literal: html.into_bytes(),
+        });
+    }
+}
@@ -0,0 +1,236 @@
+//! Filters and functions that blake adds to the template language.
+
+use std::collections::HashMap;
+
+use chrono::prelude::*;
+use chrono_tz::Tz;
+use comrak::ComrakOptions;
+use tera::{Tera, Value};
+
+use super::assets::Assets;
+use super::html::{self, Post};
+use super::urls::Urls;
+use crate::config::StatsConfig;
+
+/// Register the filters and functions that don't depend on the posts.
+pub fn register(tera: &mut Tera, urls: &Urls, timezone: Tz) {
+    tera.register_filter("markdown", markdown);
+    tera.register_filter("reading_time", reading_time);
+    tera.register_function("url", make_url(urls.clone()));
+    tera.register_function("asset_url", make_asset_url(urls.clone(), Assets::default()));
+    tera.register_function("format_date", make_format_date(timezone));
+}
+
+/// Register the functions that find static files, given their hashed names.
+pub fn register_assets(tera: &mut Tera, urls: &Urls, assets: &Assets) {
+    tera.register_function("asset_url", make_asset_url(urls.clone(), assets.clone()));
+}
+
+/// Register the functions that look things up in the posts, given all the
+/// posts, newest first.
+pub fn register_posts(tera: &mut Tera, urls: &Urls, posts: &[Post]) -> tera::Result<()> {
+    let mut post_urls = HashMap::new();
+    let mut all_posts = Vec::new();
+    for post in posts {
+        post_urls.insert(post.name().to_owned(), urls.path(post.url()));
+        all_posts.push((post.tags().to_vec(), tera::to_value(post)?));
+    }
+    tera.register_function("url_for", make_url_for(post_urls));
+    tera.register_function("get_posts", make_get_posts(all_posts));
+    Ok(())
+}
+
+/// Render a snippet of Markdown, like `{{ site.extra.bio | markdown }}`.
+fn markdown(value: Value, _: HashMap<String, Value>) -> tera::Result<Value> {
+    let text = match value {
+        Value::String(text) => text,
+        _ => return Err("markdown filter can only be used on strings".into()),
+    };
+    let options = ComrakOptions {
+        ext_strikethrough: true,
+        smart: true,
+        ..ComrakOptions::default()
+    };
+    Ok(Value::String(comrak::markdown_to_html(&text, &options)))
+}
+
+/// Estimate the minutes it takes to read some text, or a number of words.
+///
+/// `words_per_minute` sets the reading speed.
+fn reading_time(value: Value, args: HashMap<String, Value>) -> tera::Result<Value> {
+    let word_count = match value {
+        Value::String(text) => text.split_whitespace().count(),
+        Value::Number(ref number) if number.is_u64() => number.as_u64().unwrap_or(0) as usize,
+        _ => return Err("reading_time filter needs text or a number of words".into()),
+    };
+    let mut stats = StatsConfig::default();
+    match args.get("words_per_minute") {
+        None => {}
+        Some(Value::Number(number)) if number.is_u64() => {
+            stats.words_per_minute = number.as_u64().unwrap_or(0) as usize
+        }
+        Some(_) => return Err("reading_time `words_per_minute` must be a whole number".into()),
+    }
+    Ok(Value::from(html::reading_time(word_count, &stats)))
+}
+
+/// Make the `url` function.
+///
+/// `url(path="/posts/foo.html")` gives the path to a page on the site, with
+/// any prefix the site is served from. Add `absolute=true` for the full URL,
+/// for feeds and other places that will be read away from the site.
+fn make_url(urls: Urls) -> tera::GlobalFn {
+    Box::new(move |args| {
+        let path = string_arg(&args, "url", "path")?;
+        let url = if bool_arg(&args, "url", "absolute")? {
+            urls.absolute(path)
+        } else {
+            urls.path(path)
+        };
+        Ok(Value::String(url))
+    })
+}
+
+/// Make the `asset_url` function.
+///
+/// `asset_url(path="tufte.css")` gives the URL of a file in the static
+/// directory, under its hashed name if static files are fingerprinted.
+fn make_asset_url(urls: Urls, assets: Assets) -> tera::GlobalFn {
+    Box::new(move |args| {
+        let path = string_arg(&args, "asset_url", "path")?;
+        let url = format!("/static/{}", path.trim_start_matches('/'));
+        Ok(Value::String(urls.path(&assets.url(&url))))
+    })
+}
+
+/// Make the `format_date` function.
+///
+/// `format_date(date=post.date, format="%d %B %Y")` formats a timestamp in the
+/// site's timezone. The format defaults to `%Y-%m-%d`.
+fn make_format_date(timezone: Tz) -> tera::GlobalFn {
+    Box::new(move |args| {
+        let date = string_arg(&args, "format_date", "date")?;
+        let format = match args.get("format") {
+            None => "%Y-%m-%d",
+            Some(Value::String(format)) => format,
+            Some(_) => return Err("format_date() argument `format` must be a string".into()),
+        };
+        let date = DateTime::parse_from_rfc3339(date)
+            .map_err(|e| format!("format_date() cannot parse date {}: {}", date, e))?;
+        let date = date.with_timezone(&timezone);
+        Ok(Value::String(date.format(format).to_string()))
+    })
+}
+
+/// Make the `url_for` function.
+///
+/// `url_for(post="2019-11-25-my-title")` gives the URL of the post with that
+/// name, so templates don't need to know the permalink scheme.
+fn make_url_for(post_urls: HashMap<String, String>) -> tera::GlobalFn {
+    Box::new(move |args| {
+        let name = string_arg(&args, "url_for", "post")?;
+        match post_urls.get(name) {
+            Some(url) => Ok(Value::String(url.clone())),
+            None => Err(format!("url_for() cannot find post: {}", name).into()),
+        }
+    })
+}
+
+/// Make the `get_posts` function.
+///
+/// `get_posts(tag="rust", limit=5)` gives the newest posts, optionally only
+/// those with a tag, and at most `limit` of them.
+fn make_get_posts(posts: Vec<(Vec<String>, Value)>) -> tera::GlobalFn {
+    Box::new(move |args| {
+        let tag = match args.get("tag") {
+            None => None,
+            Some(Value::String(tag)) => Some(tag),
+            Some(_) => return Err("get_posts() argument `tag` must be a string".into()),
+        };
+        let limit = match args.get("limit") {
+            None => posts.len(),
+            Some(Value::Number(limit)) if limit.is_u64() => limit.as_u64().unwrap_or(0) as usize,
+            Some(_) => return Err("get_posts() argument `limit` must be a whole number".into()),
+        };
+        let posts = posts
+            .iter()
+            .filter(|(tags, _)| tag.is_none_or(|tag| tags.contains(tag)))
+            .take(limit)
+            .map(|(_, post)| post.clone())
+            .collect();
+        Ok(Value::Array(posts))
+    })
+}
+
+fn string_arg<'a>(
+    args: &'a HashMap<String, Value>,
+    function: &str,
+    name: &str,
+) -> tera::Result<&'a str> {
+    match args.get(name) {
+        Some(Value::String(value)) => Ok(value),
+        _ => Err(format!("{}() requires a string `{}` argument", function, name).into()),
+    }
+}
+
+fn bool_arg(args: &HashMap<String, Value>, function: &str, name: &str) -> tera::Result<bool> {
+    match args.get(name) {
+        None => Ok(false),
+        Some(Value::Bool(value)) => Ok(*value),
+        Some(_) => Err(format!("{}() argument `{}` must be a boolean", function, name).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str, tera: &mut Tera) -> String {
+        tera.add_raw_template("test.txt", template).unwrap();
+        tera.render("test.txt", &tera::Context::new()).unwrap()
+    }
+
+    fn make_tera() -> Tera {
+        let mut tera = Tera::default();
+        register(
+            &mut tera,
+            &Urls::new("https://example.org/notebook/", None),
+            Tz::America__New_York,
+        );
+        tera
+    }
+
+    #[test]
+    fn test_filters() {
+        let mut tera = make_tera();
+        assert_eq!(
+            render(
+                "{{ \"Some *text*\" | markdown }}|{{ 401 | reading_time }}",
+                &mut tera
+            ),
+            "<p>Some <em>text</em></p>\n|3"
+        );
+    }
+
+    #[test]
+    fn test_functions() {
+        let mut tera = make_tera();
+        assert_eq!(
+            render(
+                "{{ asset_url(path=\"tufte.css\") }} \
+                 {{ format_date(date=\"2019-11-26T02:30:00+00:00\", format=\"%Y-%m-%d %H:%M\") }}",
+                &mut tera
+            ),
+            "/notebook/static/tufte.css 2019-11-25 21:30"
+        );
+    }
+
+    #[test]
+    fn test_url_for_unknown_post() {
+        let mut tera = make_tera();
+        register_posts(&mut tera, &Urls::default(), &[]).unwrap();
+        tera.add_raw_template("test.txt", "{{ url_for(post=\"nope\") }}")
+            .unwrap();
+        assert!(tera.render("test.txt", &tera::Context::new()).is_err());
+    }
+}
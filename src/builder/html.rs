@@ -1,70 +1,411 @@
-use std::error::Error;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use chrono::prelude::*;
-use comrak;
-use comrak::nodes::{NodeHeading, NodeValue};
-use comrak::ComrakOptions;
-use lazy_static::lazy_static;
-use serde;
-use tera::{compile_templates, Tera};
+use comrak::nodes::{AstNode, NodeHeading, NodeValue};
+use comrak::{Arena, ComrakOptions};
 
+use super::assets::{Assets, Source};
+use super::figures::Figure;
+use super::images;
+use super::permalink::Permalink;
+use super::renderer::Renderer;
 use super::sidenotes;
-use crate::posts;
+use super::static_files;
+use super::urls::{self, Urls};
+use crate::config::{Config, StatsConfig, TitleConfig, TitleSource};
+use crate::error::{Error, Result};
+use crate::front_matter::{self, FrontMatter};
+use crate::{pages, posts};
 
-lazy_static! {
-    pub static ref TERA: Tera = compile_templates!("templates/*.html");
-}
+/// Marks the end of the summary in a post's Markdown.
+const MORE_MARKER: &str = "<!-- more -->";
 
 #[derive(serde::Serialize)]
 pub struct Post {
+    /// The name of the post's file, without the extension.
+    name: String,
+    /// The Markdown file the post was rendered from.
+    #[serde(skip)]
+    path: PathBuf,
     body: String,
-    title: Option<String>,
-    date: DateTime<Utc>,
+    title: Option<Title>,
+    summary: Option<String>,
+    word_count: usize,
+    reading_time: usize,
+    /// When the post was written, in the site's timezone.
+    date: DateTime<FixedOffset>,
     url: String,
+    tags: Vec<String>,
+    #[serde(skip)]
+    aliases: Vec<String>,
+    #[serde(skip)]
+    assets: Vec<Asset>,
+    #[serde(skip)]
+    images: Vec<images::Variant>,
+}
+
+/// The files in a post's own directory, if it has one, and where they're
+/// published.
+pub fn bundle_files(post: &posts::Post, permalink: &Permalink) -> Result<Vec<Asset>> {
+    let bundle = match post.bundle() {
+        Some(bundle) => bundle,
+        None => return Ok(Vec::new()),
+    };
+    let bundle_url = permalink.bundle_url();
+    let files = post
+        .bundle_files()
+        .map_err(|e| Error::Io(bundle.to_owned(), e))?;
+    Ok(files
+        .into_iter()
+        .map(|file| {
+            let url = file
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            Asset {
+                source: bundle.join(&file),
+                url: format!("{}{}", bundle_url, url),
+            }
+        })
+        .collect())
+}
+
+/// A file that belongs to a post, to be copied next to it.
+pub struct Asset {
+    pub source: PathBuf,
+    pub url: String,
+}
+
+/// The Markdown file being rendered, and the files a post has of its own,
+/// if it's a directory.
+#[derive(Default)]
+struct Files {
+    /// The Markdown file, for saying where any problems are.
+    path: PathBuf,
+    /// Distinguishes the ids in the post's HTML from those of other posts
+    /// shown on the same page.
+    id: String,
+    /// Where the post or page is published, for resolving relative URLs.
+    url: String,
+    /// Where the files are published.
+    bundle_url: Option<String>,
+    assets: Vec<Asset>,
 }
 
 impl Post {
-    pub fn render(post: &posts::Post) -> Result<Post, Box<dyn Error>> {
-        let contents = fs::read_to_string(&post.path())?;
-        let (title, body) = render_markdown(&contents)?;
+    pub fn render(
+        post: &posts::Post,
+        permalink: &Permalink,
+        config: &Config,
+        assets: &Assets,
+    ) -> Result<Post> {
+        let contents =
+            fs::read_to_string(post.path()).map_err(|e| Error::Io(post.path().to_owned(), e))?;
+        let files = Files {
+            path: post.path().to_owned(),
+            id: posts::slugify(post.name()),
+            url: permalink.url().to_owned(),
+            bundle_url: post.bundle().map(|_| permalink.bundle_url()),
+            assets: bundle_files(post, permalink)?,
+        };
+        let rendered = render_markdown(&contents, config, &files, assets)?;
+        log::trace!(
+            "Rendered {}: {} words, {} files, {} image copies",
+            post.path().display(),
+            rendered.word_count,
+            files.assets.len(),
+            rendered.images.len()
+        );
+        let date = post.date().with_timezone(&post.date().offset().fix());
+        let title = match rendered.title {
+            Some(title) => Some(title),
+            None if config.title.sources.contains(&TitleSource::Date) => {
+                Some(Title::from_date(&date))
+            }
+            None => None,
+        };
         Ok(Post {
-            body,
+            name: post.name().to_owned(),
+            path: post.path().to_owned(),
+            body: rendered.body,
             title,
-            date: post.date().clone(),
-            // TODO: This violates abstraction. render() doesn't know where
-            // the post will be written to. Ideally, would store the original
-            // post in html::Post, but that creates weird Serde trait errors
-            // that I can't debug.
-            url: format!("/posts/{}.html", post.name()),
+            summary: rendered.summary,
+            word_count: rendered.word_count,
+            reading_time: reading_time(rendered.word_count, &config.stats),
+            date,
+            url: permalink.url().to_owned(),
+            tags: rendered.tags,
+            aliases: rendered.aliases,
+            assets: files.assets,
+            images: rendered.images,
         })
     }
 
-    pub fn write_html(&self, dest_file: &Path) -> Result<(), Box<dyn Error>> {
-        let mut context = tera::Context::new();
+    /// Write the post's page, with links to the posts before and after it.
+    ///
+    /// Returns whether the page changed.
+    pub fn write_html(
+        &self,
+        renderer: &Renderer,
+        previous: Option<&Post>,
+        next: Option<&Post>,
+        dest_file: &Path,
+    ) -> Result<bool> {
+        let mut context = renderer.context();
+        context.insert("page_url", &self.url);
+        context.insert("previous", &previous.map(PostLink::new));
+        context.insert("next", &next.map(PostLink::new));
         context.insert("post", &self.body);
         context.insert("title", &self.title);
         context.insert("date", &self.date.format("%Y-%m-%d").to_string());
-        let rendered = TERA.render("post.html", &context)?;
-        fs::write(dest_file, rendered)?;
-        Ok(())
+        context.insert("timestamp", &self.date.to_rfc3339());
+        context.insert("word_count", &self.word_count);
+        context.insert("reading_time", &self.reading_time);
+        context.insert("tags", &self.tags);
+        renderer.write_page("post.html", &context, &self.url, dest_file)
     }
 
-    pub fn date(&self) -> &DateTime<Utc> {
+    pub fn date(&self) -> &DateTime<FixedOffset> {
         &self.date
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    /// The post's own files.
+    pub fn assets(&self) -> &[Asset] {
+        &self.assets
+    }
+
+    /// Smaller copies of the post's images.
+    pub fn images(&self) -> &[images::Variant] {
+        &self.images
+    }
+
+    /// Other URLs that should redirect to this post.
+    pub fn aliases(&self) -> &[String] {
+        &self.aliases
+    }
+
+    pub fn title(&self) -> Option<&Title> {
+        self.title.as_ref()
+    }
+
+    pub fn word_count(&self) -> usize {
+        self.word_count
+    }
+}
+
+/// A standalone page, like About, rendered to HTML.
+pub struct Page {
+    title: Option<Title>,
+    body: String,
+    url: String,
+    images: Vec<images::Variant>,
+}
+
+impl Page {
+    pub fn render(page: &pages::Page, config: &Config, assets: &Assets) -> Result<Page> {
+        let contents =
+            fs::read_to_string(page.path()).map_err(|e| Error::Io(page.path().to_owned(), e))?;
+        let files = Files {
+            path: page.path().to_owned(),
+            id: posts::slugify(page.url()),
+            url: page.url().to_owned(),
+            ..Files::default()
+        };
+        let rendered = render_markdown(&contents, config, &files, assets)?;
+        Ok(Page {
+            title: rendered.title,
+            body: rendered.body,
+            url: page.url().to_owned(),
+            images: rendered.images,
+        })
+    }
+
+    pub fn write_html(&self, renderer: &Renderer, dest_file: &Path) -> Result<bool> {
+        let mut context = renderer.context();
+        context.insert("page_url", &self.url);
+        context.insert("page", &self.body);
+        context.insert("title", &self.title);
+        renderer.write_page("page.html", &context, &self.url, dest_file)
+    }
+
+    pub fn title(&self) -> Option<&Title> {
+        self.title.as_ref()
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Smaller copies of the page's images.
+    pub fn images(&self) -> &[images::Variant] {
+        &self.images
+    }
+}
+
+/// Just enough about a post to link to it.
+#[derive(serde::Serialize)]
+struct PostLink<'a> {
+    title: Option<&'a Title>,
+    date: String,
+    url: &'a str,
+}
+
+impl<'a> PostLink<'a> {
+    fn new(post: &'a Post) -> PostLink<'a> {
+        PostLink {
+            title: post.title.as_ref(),
+            date: post.date.to_rfc3339(),
+            url: &post.url,
+        }
+    }
+}
+
+/// The title of a post.
+///
+/// Titles can contain inline formatting, such as code or emphasis, so we keep
+/// both the rendered HTML, for the page itself, and plain text, for places
+/// like the `<title>` element that can't contain markup.
+#[derive(Debug, PartialEq, serde::Serialize)]
+pub struct Title {
+    text: String,
+    html: String,
+}
+
+impl Title {
+    /// Make a title from a sequence of inline Markdown nodes.
+    fn from_inlines<'a, I>(nodes: I, options: &ComrakOptions) -> Option<Title>
+    where
+        I: Iterator<Item = &'a AstNode<'a>>,
+    {
+        let mut text = vec![];
+        let mut html = String::new();
+        for node in nodes {
+            collect_text(node, &mut text);
+            html.push_str(&format_html(node, options));
+        }
+        let text = String::from_utf8_lossy(&text);
+        if text.trim().is_empty() {
+            return None;
+        }
+        Some(Title {
+            text: text.trim().to_owned(),
+            html: html.trim().to_owned(),
+        })
+    }
+
+    /// Make a title by parsing a single line of Markdown.
+    fn from_markdown<'a>(
+        arena: &'a Arena<AstNode<'a>>,
+        markdown: &str,
+        options: &ComrakOptions,
+    ) -> Option<Title> {
+        let root = comrak::parse_document(arena, markdown, options);
+        match find_first_paragraph(root) {
+            None => None,
+            Some(paragraph) => Title::from_inlines(paragraph.children(), options),
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    fn from_date(date: &DateTime<FixedOffset>) -> Title {
+        let text = date.format("%Y-%m-%d").to_string();
+        Title {
+            html: text.clone(),
+            text,
+        }
+    }
 }
 
-pub fn write_index_html(posts: &[Post], index_page: &Path) -> Result<(), Box<dyn Error>> {
-    let mut context = tera::Context::new();
+pub fn write_index_html(
+    renderer: &Renderer,
+    posts: &[Post],
+    config: &Config,
+    index_page: &Path,
+) -> Result<bool> {
+    let mut context = renderer.context();
+    context.insert("page_url", "/");
+    context.insert("posts", posts);
+    context.insert("summary", &config.summary);
+    renderer.write_page("index.html", &context, "/", index_page)
+}
+
+/// Write an Atom feed of the given posts, newest first.
+pub fn write_feed(
+    renderer: &Renderer,
+    posts: &[Post],
+    config: &Config,
+    feed_page: &Path,
+) -> Result<()> {
+    if config.base_url.is_empty() {
+        // Atom needs absolute links and ids, so readers will reject the feed.
+        log::warn!("base_url is not set, so the feed has relative links");
+    }
+    let updated = match posts.first() {
+        Some(post) => post.date.to_rfc3339(),
+        None => Utc::now().with_timezone(&config.timezone).to_rfc3339(),
+    };
+    let mut context = renderer.context();
     context.insert("posts", posts);
-    let rendered = TERA.render("index.html", &context)?;
-    fs::write(index_page, rendered)?;
+    context.insert("summary", &config.summary);
+    context.insert("updated", &updated);
+    let rendered = renderer
+        .render("feed.xml", &context)
+        .map_err(|e| Error::Template(feed_page.to_owned(), e))?;
+    static_files::write_if_changed(rendered.as_bytes(), feed_page)?;
     Ok(())
 }
 
-fn render_markdown(contents: &str) -> Result<(Option<String>, String), Box<dyn Error>> {
+/// Write a page at `from` that redirects to the URL `to`.
+pub fn write_redirect(renderer: &Renderer, from: &str, to: &str, dest_file: &Path) -> Result<bool> {
+    let mut context = renderer.context();
+    context.insert("target", to);
+    renderer.write_page("redirect.html", &context, from, dest_file)
+}
+
+/// A post rendered from Markdown.
+struct Rendered {
+    title: Option<Title>,
+    summary: Option<String>,
+    body: String,
+    word_count: usize,
+    tags: Vec<String>,
+    aliases: Vec<String>,
+    images: Vec<images::Variant>,
+}
+
+/// Render a post or page from Markdown.
+///
+/// `files` are the post's own files, if it has any, and `assets` the names
+/// the static files are published under.
+fn render_markdown(
+    contents: &str,
+    config: &Config,
+    files: &Files,
+    assets: &Assets,
+) -> Result<Rendered> {
+    let (front_matter, contents) = front_matter::split(contents)
+        .map_err(|e| Error::post(&files.path, posts::Error::InvalidFrontMatter(e)))?;
     let arena = comrak::Arena::new();
     let options = ComrakOptions {
         ext_footnotes: true,
@@ -73,62 +414,467 @@ fn render_markdown(contents: &str) -> Result<(Option<String>, String), Box<dyn E
         ..ComrakOptions::default()
     };
     let root = comrak::parse_document(&arena, contents, &options);
-    let title = find_title(root).map(|s| s.to_owned());
-    let mut html = vec![];
-    sidenotes::render(&arena, root, &options)?;
-    let options = ComrakOptions {
+    let word_count = count_words(root, &config.stats);
+    let links = Links {
+        config,
+        files,
+        assets,
+        urls: Urls::from_config(config),
+    };
+    let mut images = links.resolve(root)?;
+    sidenotes::render(&arena, root, &options)
+        .map_err(|e| Error::Markdown(files.path.clone(), e))?;
+    let render_options = ComrakOptions {
         unsafe_: true,
         ext_footnotes: false,
-        ..options
+        ..options.clone()
+    };
+    let title = find_title(&arena, root, &front_matter, &config.title, &render_options);
+    let summary = find_summary(
+        root,
+        contents,
+        &front_matter,
+        &links,
+        &mut images,
+        &options,
+        &render_options,
+    )?;
+    Ok(Rendered {
+        title,
+        summary,
+        body: format_html(root, &render_options),
+        word_count,
+        tags: front_matter.tags,
+        aliases: front_matter.aliases,
+        images,
+    })
+}
+
+/// Escape text to go in a double-quoted HTML attribute.
+fn escape_attribute(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// How to resolve the links and images in a post.
+struct Links<'a> {
+    config: &'a Config,
+    files: &'a Files,
+    assets: &'a Assets,
+    urls: Urls,
+}
+
+impl<'a> Links<'a> {
+    /// Make the links under `root` work where the site is published, and
+    /// turn images into responsive HTML.
+    ///
+    /// Returns the smaller copies of images that need to be made.
+    fn resolve<'b>(&self, root: &'b AstNode<'b>) -> Result<Vec<images::Variant>> {
+        if let Some(bundle_url) = &self.files.bundle_url {
+            resolve_bundle_links(root, bundle_url);
+        }
+        let variants = self.render_images(root)?;
+        self.prefix_links(root);
+        Ok(variants)
+    }
+
+    /// Where a page or file on the site is published: with the site's path
+    /// prefix, and under its hashed name if it's a fingerprinted static file.
+    fn site_url(&self, url: &str) -> String {
+        self.urls.path(&self.assets.url(url))
+    }
+
+    /// Make links in the post that point to other pages or files on the site
+    /// point to where they're published.
+    fn prefix_links<'b>(&self, root: &'b AstNode<'b>) {
+        for node in root.descendants() {
+            match &mut node.data.borrow_mut().value {
+                NodeValue::Link(link) | NodeValue::Image(link) => {
+                    let url = String::from_utf8_lossy(&link.url).into_owned();
+                    if url.starts_with('/') {
+                        link.url = self.site_url(&url).into_bytes();
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Replace images with HTML that gives their size, loads them lazily,
+    /// and offers smaller copies to narrow screens. Images with captions
+    /// become figures.
+    ///
+    /// If images aren't to be processed, only figures are replaced.
+    fn render_images<'b>(&self, root: &'b AstNode<'b>) -> Result<Vec<images::Variant>> {
+        let image_nodes = root
+            .descendants()
+            .filter(|node| matches!(node.data.borrow().value, NodeValue::Image(_)))
+            .collect::<Vec<_>>();
+        let mut variants = Vec::new();
+        let mut figures = 0;
+        for node in image_nodes {
+            let (url, title) = match &node.data.borrow().value {
+                NodeValue::Image(link) => (
+                    String::from_utf8_lossy(&link.url).into_owned(),
+                    String::from_utf8_lossy(&link.title).into_owned(),
+                ),
+                _ => continue,
+            };
+            let figure = Figure::find(node, &title)
+                .map_err(|e| Error::Markdown(self.files.path.clone(), e))?;
+            if figure.is_none() && !self.config.images.process {
+                continue;
+            }
+            let mut alt = vec![];
+            for child in node.children() {
+                collect_text(child, &mut alt);
+            }
+            // Relative images in posts with their own files have already been
+            // resolved, so any left are relative to the post or page.
+            let published = if urls::is_relative(&url) {
+                urls::resolve(&self.files.url, &url)
+            } else {
+                url.clone()
+            };
+            let mut attributes = vec![
+                ("src", self.site_url(&published)),
+                ("alt", String::from_utf8_lossy(&alt).into_owned()),
+            ];
+            if figure.is_none() && !title.is_empty() {
+                attributes.push(("title", title));
+            }
+            let is_local =
+                urls::is_relative(&url) || (url.starts_with('/') && !url.starts_with("//"));
+            if self.config.images.process && is_local {
+                let source = self.image_source(&published).ok_or_else(|| {
+                    Error::Asset(
+                        self.files.path.clone(),
+                        format!("Cannot find image: {}", url),
+                    )
+                })?;
+                // Images blake has itself can't be read from a file, so they
+                // are shown as they are.
+                let image = match source {
+                    Source::File(source) => images::Image::inspect(
+                        &source,
+                        // The copies are named after the published image, so
+                        // they get new names whenever a fingerprinted image
+                        // changes.
+                        &self.assets.url(&published),
+                        &self.config.images.widths,
+                    )?,
+                    Source::Builtin => images::Image::default(),
+                };
+                if let Some((width, height)) = image.size {
+                    attributes.push(("width", width.to_string()));
+                    attributes.push(("height", height.to_string()));
+                    if !image.variants.is_empty() {
+                        let mut srcset = image
+                            .variants
+                            .iter()
+                            .map(|v| format!("{} {}w", self.urls.path(&v.url), v.width))
+                            .collect::<Vec<_>>();
+                        srcset.push(format!("{} {}w", self.site_url(&published), width));
+                        attributes.push(("srcset", srcset.join(", ")));
+                    }
+                }
+                variants.extend(image.variants);
+            }
+            if self.config.images.process && self.config.images.lazy {
+                attributes.push(("loading", String::from("lazy")));
+            }
+            let mut html = String::from("<img");
+            for (name, value) in attributes {
+                html.push_str(&format!(" {}=\"{}\"", name, escape_attribute(&value)));
+            }
+            html.push_str(" />");
+            let children = node.children().collect::<Vec<_>>();
+            for child in children {
+                child.detach();
+            }
+            match figure {
+                Some(figure) => {
+                    figures += 1;
+                    let id = format!("mn-figure-{}-{}", self.files.id, figures);
+                    figure.render(&html, &id);
+                }
+                None => node.data.borrow_mut().value = NodeValue::HtmlInline(html.into_bytes()),
+            }
+        }
+        Ok(variants)
+    }
+
+    /// Find the file for an image on the site, the same way as the files
+    /// are published: the post's own files, the site's static files, the
+    /// theme's, and then any post's files.
+    fn image_source(&self, url: &str) -> Option<Source> {
+        match self.files.assets.iter().find(|asset| asset.url == url) {
+            Some(asset) => Some(Source::File(asset.source.clone())),
+            None => self.assets.source(url).cloned(),
+        }
+    }
+}
+
+/// Make relative links in a post with its own files point to where the
+/// files are copied.
+fn resolve_bundle_links<'a>(root: &'a AstNode<'a>, bundle_url: &str) {
+    for node in root.descendants() {
+        match &mut node.data.borrow_mut().value {
+            NodeValue::Link(link) | NodeValue::Image(link) => {
+                let url = String::from_utf8_lossy(&link.url).into_owned();
+                if urls::is_relative(&url) {
+                    let url = format!("{}{}", bundle_url, url.trim_start_matches("./"));
+                    link.url = url.into_bytes();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Count the words in a post.
+///
+/// Must be called before sidenotes are rendered, because that turns their
+/// content into raw HTML.
+fn count_words<'a>(node: &'a AstNode<'a>, config: &StatsConfig) -> usize {
+    match &node.data.borrow().value {
+        NodeValue::Text(text) | NodeValue::Code(text) => {
+            return String::from_utf8_lossy(text).split_whitespace().count();
+        }
+        NodeValue::CodeBlock(block) if config.count_code => {
+            return String::from_utf8_lossy(&block.literal)
+                .split_whitespace()
+                .count();
+        }
+        NodeValue::CodeBlock(_) => return 0,
+        NodeValue::FootnoteDefinition(_) if !config.count_sidenotes => return 0,
+        _ => {}
+    }
+    node.children()
+        .map(|child| count_words(child, config))
+        .sum()
+}
+
+/// Estimate how many minutes it takes to read a number of words.
+pub fn reading_time(word_count: usize, config: &StatsConfig) -> usize {
+    word_count.div_ceil(config.words_per_minute.max(1))
+}
+
+/// Find the summary of the post, rendered as HTML.
+///
+/// An explicit summary in the front matter wins, followed by everything
+/// before a `<!-- more -->` marker. Failing those, we might use the first
+/// paragraph of the post, without any of its sidenotes.
+///
+/// Adds any smaller copies of images that only the summary shows to `images`.
+fn find_summary<'a>(
+    root: &'a AstNode<'a>,
+    contents: &str,
+    front_matter: &FrontMatter,
+    links: &Links,
+    images: &mut Vec<images::Variant>,
+    parse_options: &ComrakOptions,
+    render_options: &ComrakOptions,
+) -> Result<Option<String>> {
+    if let Some(summary) = &front_matter.summary {
+        let arena = Arena::new();
+        let document = comrak::parse_document(&arena, summary, parse_options);
+        images.extend(links.resolve(document)?);
+        return Ok(Some(format_html(document, render_options)));
+    }
+    if let Some(marker) = find_more_marker(root) {
+        let html = root
+            .children()
+            .take_while(|node| !std::ptr::eq(*node, marker))
+            .map(|node| format_html(node, render_options))
+            .collect();
+        marker.detach();
+        return Ok(Some(html));
+    }
+    if links.config.summary.auto_excerpt && root.children().count() > 1 {
+        return excerpt(contents, links, parse_options, render_options);
+    }
+    Ok(None)
+}
+
+/// Find the `<!-- more -->` marker that separates the summary from the rest
+/// of the post.
+fn find_more_marker<'a>(root: &'a AstNode<'a>) -> Option<&'a AstNode<'a>> {
+    root.children()
+        .find(|node| match &node.data.borrow().value {
+            NodeValue::HtmlBlock(block) => {
+                String::from_utf8_lossy(&block.literal).trim() == MORE_MARKER
+            }
+            _ => false,
+        })
+}
+
+/// Render the first paragraph of a post, without its sidenotes.
+///
+/// By the time we want an excerpt, the footnote references in the main
+/// document have already been replaced with sidenotes, so we parse the post
+/// again to get a paragraph that we're free to take them out of.
+fn excerpt(
+    contents: &str,
+    links: &Links,
+    parse_options: &ComrakOptions,
+    render_options: &ComrakOptions,
+) -> Result<Option<String>> {
+    let arena = comrak::Arena::new();
+    let root = comrak::parse_document(&arena, contents, parse_options);
+    let paragraph = match find_first_paragraph(root) {
+        None => return Ok(None),
+        Some(paragraph) => paragraph,
     };
-    comrak::format_html(root, &options, &mut html)?;
-    let html_str = String::from_utf8(html)?;
-    Ok((title, html_str))
+    let references = paragraph
+        .descendants()
+        .filter(|node| matches!(node.data.borrow().value, NodeValue::FootnoteReference(_)))
+        .collect::<Vec<_>>();
+    for reference in references {
+        reference.detach();
+    }
+    links.resolve(paragraph)?;
+    Ok(Some(format_html(paragraph, render_options)))
 }
 
 /// Find the title in the post.
 ///
-/// Assumes that the first Heading 1 is the title.
-fn find_title<'a>(root: &'a comrak::nodes::AstNode<'a>) -> Option<String> {
-    for node in root.descendants() {
-        if let NodeValue::Heading(NodeHeading { level: 1, .. }) = node.data.borrow().value {
-            // TODO: Is there a way to avoid the clone?
-            let text = node
-                .children()
-                .filter_map(|child| child.data.borrow().value.text().cloned())
-                .flatten()
-                .collect::<Vec<u8>>();
-            return String::from_utf8(text).ok();
+/// Tries each of the configured sources in turn, except for the date, which
+/// the caller has to handle. If the title comes from a heading, that heading
+/// might be removed from the document, so it doesn't appear twice.
+fn find_title<'a>(
+    arena: &'a Arena<AstNode<'a>>,
+    root: &'a AstNode<'a>,
+    front_matter: &FrontMatter,
+    config: &TitleConfig,
+    options: &ComrakOptions,
+) -> Option<Title> {
+    for source in &config.sources {
+        let title = match source {
+            TitleSource::Heading => match find_heading(root) {
+                None => None,
+                Some(heading) => {
+                    let title = Title::from_inlines(heading.children(), options);
+                    if title.is_some() && config.remove_heading {
+                        heading.detach();
+                    }
+                    title
+                }
+            },
+            TitleSource::FrontMatter => match &front_matter.title {
+                None => None,
+                Some(title) => Title::from_markdown(arena, title, options),
+            },
+            TitleSource::FirstLine => match find_first_paragraph(root) {
+                None => None,
+                Some(paragraph) => {
+                    let first_line = paragraph.children().take_while(|child| {
+                        !matches!(
+                            child.data.borrow().value,
+                            NodeValue::SoftBreak | NodeValue::LineBreak
+                        )
+                    });
+                    Title::from_inlines(first_line, options)
+                }
+            },
+            TitleSource::Date => None,
+        };
+        if title.is_some() {
+            return title;
         }
     }
     None
 }
 
+/// Find the first Heading 1 in the post.
+fn find_heading<'a>(root: &'a AstNode<'a>) -> Option<&'a AstNode<'a>> {
+    root.descendants().find(|node| {
+        matches!(
+            node.data.borrow().value,
+            NodeValue::Heading(NodeHeading { level: 1, .. })
+        )
+    })
+}
+
+/// Find the first top-level paragraph in the post.
+fn find_first_paragraph<'a>(root: &'a AstNode<'a>) -> Option<&'a AstNode<'a>> {
+    root.children()
+        .find(|node| matches!(node.data.borrow().value, NodeValue::Paragraph))
+}
+
+/// Collect the plain text of a node and all its descendants.
+fn collect_text<'a>(node: &'a AstNode<'a>, text: &mut Vec<u8>) {
+    for descendant in node.descendants() {
+        match &descendant.data.borrow().value {
+            NodeValue::Text(t) | NodeValue::Code(t) => text.extend(t),
+            NodeValue::SoftBreak | NodeValue::LineBreak => text.push(b' '),
+            _ => {}
+        }
+    }
+}
+
+/// Render a node and its descendants as HTML.
+fn format_html<'a>(node: &'a AstNode<'a>, options: &ComrakOptions) -> String {
+    let mut html = vec![];
+    comrak::format_html(node, options, &mut html).expect("Writing to a Vec cannot fail.");
+    // comrak only writes what it parsed, which came from a string.
+    String::from_utf8_lossy(&html).into_owned()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::TempDir;
+
+    fn title_of(contents: &str, title: TitleConfig) -> Option<Title> {
+        let config = Config {
+            title,
+            ..Config::default()
+        };
+        render_markdown(contents, &config, &Files::default(), &Assets::default())
+            .unwrap()
+            .title
+    }
+
+    fn summary_of(contents: &str) -> Option<String> {
+        render_markdown(
+            contents,
+            &Config::default(),
+            &Files::default(),
+            &Assets::default(),
+        )
+        .unwrap()
+        .summary
+    }
+
+    fn text_title(text: &str) -> Option<Title> {
+        Some(Title {
+            text: String::from(text),
+            html: String::from(text),
+        })
+    }
 
     #[test]
     fn test_no_title() {
-        let arena = comrak::Arena::new();
         let contents = "no heading here";
-        let root = comrak::parse_document(&arena, contents, &ComrakOptions::default());
-        assert_eq!(find_title(&root), None)
+        assert_eq!(title_of(contents, TitleConfig::default()), None)
     }
 
     #[test]
     fn test_title() {
-        let arena = comrak::Arena::new();
         let contents = "# title
 
 paragraph text";
-        let root = comrak::parse_document(&arena, contents, &ComrakOptions::default());
-        assert_eq!(find_title(&root), Some(String::from("title")))
+        assert_eq!(
+            title_of(contents, TitleConfig::default()),
+            text_title("title")
+        )
     }
 
     #[test]
     fn test_two_titles() {
-        let arena = comrak::Arena::new();
         let contents = "# title
 
 paragraph text
@@ -137,28 +883,391 @@ paragraph text
 
 more text
 ";
-        let root = comrak::parse_document(&arena, contents, &ComrakOptions::default());
-        assert_eq!(find_title(&root), Some(String::from("title")))
+        assert_eq!(
+            title_of(contents, TitleConfig::default()),
+            text_title("title")
+        )
+    }
+
+    #[test]
+    fn test_rich_title() {
+        let contents = "# the `find_title` *function*";
+        let expected = Title {
+            text: String::from("the find_title function"),
+            html: String::from("the <code>find_title</code> <em>function</em>"),
+        };
+        assert_eq!(title_of(contents, TitleConfig::default()), Some(expected))
+    }
+
+    #[test]
+    fn test_title_removed_from_body() {
+        let contents = "# title
+
+paragraph text";
+        let rendered = render_markdown(
+            contents,
+            &Config::default(),
+            &Files::default(),
+            &Assets::default(),
+        )
+        .unwrap();
+        assert_eq!(rendered.body, "<p>paragraph text</p>\n");
+    }
+
+    #[test]
+    fn test_title_kept_in_body() {
+        let contents = "# title
+
+paragraph text";
+        let config = Config {
+            title: TitleConfig {
+                remove_heading: false,
+                ..TitleConfig::default()
+            },
+            ..Config::default()
+        };
+        let rendered =
+            render_markdown(contents, &config, &Files::default(), &Assets::default()).unwrap();
+        assert_eq!(rendered.title, text_title("title"));
+        assert_eq!(rendered.body, "<h1>title</h1>\n<p>paragraph text</p>\n");
+    }
+
+    #[test]
+    fn test_front_matter_title() {
+        let contents = "+++
+title = \"from front matter\"
++++
+
+paragraph text";
+        assert_eq!(
+            title_of(contents, TitleConfig::default()),
+            text_title("from front matter")
+        )
+    }
+
+    #[test]
+    fn test_first_line_title() {
+        let contents = "first line
+second line";
+        let config = TitleConfig {
+            sources: vec![TitleSource::Heading, TitleSource::FirstLine],
+            ..TitleConfig::default()
+        };
+        assert_eq!(title_of(contents, config), text_title("first line"))
+    }
+
+    #[test]
+    fn test_date_title() {
+        let date = FixedOffset::west(5 * 3600)
+            .ymd(2019, 11, 25)
+            .and_hms(21, 30, 0);
+        assert_eq!(Some(Title::from_date(&date)), text_title("2019-11-25"))
+    }
+
+    #[test]
+    fn test_no_summary() {
+        assert_eq!(summary_of("just one paragraph"), None)
+    }
+
+    #[test]
+    fn test_front_matter_summary() {
+        let contents = "+++
+summary = \"A *short* post.\"
++++
+
+first paragraph
+
+second paragraph";
+        assert_eq!(
+            summary_of(contents),
+            Some(String::from("<p>A <em>short</em> post.</p>\n"))
+        )
+    }
+
+    #[test]
+    fn test_front_matter_summary_links() {
+        let config = Config {
+            base_url: String::from("https://example.org/notebook/"),
+            ..Config::default()
+        };
+        let files = Files {
+            bundle_url: Some(String::from("/posts/post/")),
+            ..Files::default()
+        };
+        let contents = "+++
+summary = \"See [about](/about.html) and [data](data.csv).\"
++++
+
+first paragraph";
+        let rendered = render_markdown(contents, &config, &files, &Assets::default()).unwrap();
+        assert_eq!(
+            rendered.summary,
+            Some(String::from(
+                "<p>See <a href=\"/notebook/about.html\">about</a> and \
+                 <a href=\"/notebook/posts/post/data.csv\">data</a>.</p>\n"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_more_marker_summary() {
+        let contents = "# title
+
+first paragraph
+
+second paragraph
+
+<!-- more -->
+
+third paragraph";
+        let rendered = render_markdown(
+            contents,
+            &Config::default(),
+            &Files::default(),
+            &Assets::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            rendered.summary,
+            Some(String::from(
+                "<p>first paragraph</p>\n<p>second paragraph</p>\n"
+            ))
+        );
+        assert_eq!(
+            rendered.body,
+            "<p>first paragraph</p>\n<p>second paragraph</p>\n<p>third paragraph</p>\n"
+        );
+    }
+
+    #[test]
+    fn test_excerpt_summary() {
+        let contents = "# title
+
+I mentioned[^1] a thing.
+
+more text
+
+[^1]: The thing I mentioned
+";
+        assert_eq!(
+            summary_of(contents),
+            Some(String::from("<p>I mentioned a thing.</p>\n"))
+        )
+    }
+
+    fn word_count_of(contents: &str, stats: StatsConfig) -> usize {
+        let config = Config {
+            stats,
+            ..Config::default()
+        };
+        render_markdown(contents, &config, &Files::default(), &Assets::default())
+            .unwrap()
+            .word_count
+    }
+
+    #[test]
+    fn test_word_count() {
+        let contents = "# A title
+
+Some *words* in `a` paragraph.[^1]
+
+```
+let code = true;
+```
+
+[^1]: A sidenote.
+";
+        assert_eq!(word_count_of(contents, StatsConfig::default()), 9);
+        let config = StatsConfig {
+            count_code: true,
+            count_sidenotes: false,
+            ..StatsConfig::default()
+        };
+        assert_eq!(word_count_of(contents, config), 11);
+    }
+
+    #[test]
+    fn test_reading_time() {
+        let config = StatsConfig::default();
+        assert_eq!(reading_time(0, &config), 0);
+        assert_eq!(reading_time(1, &config), 1);
+        assert_eq!(reading_time(200, &config), 1);
+        assert_eq!(reading_time(201, &config), 2);
+    }
+
+    #[test]
+    fn test_prefix_links() {
+        let contents =
+            "[local](/posts/a.html), [remote](https://example.com/) and [relative](b.html)";
+        let config = Config {
+            base_url: String::from("https://example.org/notebook/"),
+            ..Config::default()
+        };
+        let rendered = render_markdown(contents, &config, &Files::default(), &Assets::default())
+            .unwrap()
+            .body;
+        assert_eq!(
+            rendered,
+            "<p><a href=\"/notebook/posts/a.html\">local</a>, \
+             <a href=\"https://example.com/\">remote</a> and \
+             <a href=\"b.html\">relative</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn test_bundle_links() {
+        let contents = "![diagram](./diagram.png), [data](data/raw.csv) and [top](#top)";
+        let mut config = Config {
+            base_url: String::from("https://example.org/notebook/"),
+            ..Config::default()
+        };
+        config.images.process = false;
+        let files = Files {
+            bundle_url: Some(String::from("/posts/post/")),
+            ..Files::default()
+        };
+        let rendered = render_markdown(contents, &config, &files, &Assets::default())
+            .unwrap()
+            .body;
+        assert_eq!(
+            rendered,
+            "<p><img src=\"/notebook/posts/post/diagram.png\" alt=\"diagram\" />, \
+             <a href=\"/notebook/posts/post/data/raw.csv\">data</a> and \
+             <a href=\"#top\">top</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn test_images() {
+        let root = TempDir::new("html-images");
+        let root = root.path();
+        image::RgbImage::new(1000, 500)
+            .save(root.join("plot.png"))
+            .unwrap();
+        let mut config = Config::default();
+        config.images.widths = vec![480];
+        let mut assets = Assets::default();
+        assets.add_file("/static/plot.png", &root.join("plot.png"));
+        assets.add_file("/posts/other/chart.png", &root.join("plot.png"));
+        let page = Files {
+            url: String::from("/about/me.html"),
+            ..Files::default()
+        };
+        let render = |contents, files: &Files| render_markdown(contents, &config, files, &assets);
+        let rendered = render(
+            "![A *plot*](/static/plot.png \"Plot\") and ![remote](https://example.com/a.png)",
+            &Files::default(),
+        );
+        let other_post = render("![chart](/posts/other/chart.png)", &Files::default());
+        let relative = render("![plot](../static/plot.png)", &page).unwrap();
+        let missing = render("![gone](/static/gone.png)", &Files::default());
+        let missing_relative = render("![here](plot.png)", &page);
+
+        let rendered = rendered.unwrap();
+        assert_eq!(
+            rendered.body,
+            "<p><img src=\"/static/plot.png\" alt=\"A plot\" title=\"Plot\" \
+             width=\"1000\" height=\"500\" \
+             srcset=\"/_images/static/plot-480w.png 480w, /static/plot.png 1000w\" \
+             loading=\"lazy\" /> and \
+             <img src=\"https://example.com/a.png\" alt=\"remote\" loading=\"lazy\" /></p>\n"
+        );
+        assert_eq!(
+            rendered
+                .images
+                .iter()
+                .map(|image| (image.url.as_str(), image.width, image.height))
+                .collect::<Vec<_>>(),
+            [("/_images/static/plot-480w.png", 480, 240)]
+        );
+        assert_eq!(
+            other_post.unwrap().images[0].url,
+            "/_images/posts/other/chart-480w.png"
+        );
+        assert!(relative.body.contains("src=\"/static/plot.png\""));
+        assert_eq!(relative.images[0].url, "/_images/static/plot-480w.png");
+        assert!(matches!(missing, Err(Error::Asset(..))));
+        assert!(matches!(missing_relative, Err(Error::Asset(..))));
+    }
+
+    #[test]
+    fn test_figures() {
+        let contents = "![A plot](/a.png \"What it shows\")
+
+![Wide](/b.png \"Across the page\"){.fullwidth}
+
+![Small](/c.png \"Beside\"){.margin} and text.
+
+![Small](/c.png \"Beside\"){.margin}
+";
+        let mut config = Config::default();
+        config.images.process = false;
+        let files = Files {
+            id: String::from("post"),
+            ..Files::default()
+        };
+        let rendered = render_markdown(contents, &config, &files, &Assets::default())
+            .unwrap()
+            .body;
+        assert_eq!(
+            rendered,
+            "<figure><img src=\"/a.png\" alt=\"A plot\" />\
+             <figcaption>What it shows</figcaption></figure>\n\
+             <figure class=\"fullwidth\"><img src=\"/b.png\" alt=\"Wide\" />\
+             <figcaption>Across the page</figcaption></figure>\n\
+             <p><img src=\"/c.png\" alt=\"Small\" title=\"Beside\" />{.margin} and text.</p>\n\
+             <p><span><label class=\"margin-toggle\" for=\"mn-figure-post-3\">&#8853;</label>\
+             <input class=\"margin-toggle\" id=\"mn-figure-post-3\" type=\"checkbox\"/>\
+             <span class=\"marginnote\"><img src=\"/c.png\" alt=\"Small\" />Beside</span></span></p>\n"
+        );
+        assert!(render_markdown(
+            "![x](/x.png){.huge}",
+            &config,
+            &Files::default(),
+            &Assets::default()
+        )
+        .is_err());
     }
 
     #[test]
     fn test_basic_render() {
         let contents = "here's a *thing*";
-        let (_, rendered) = render_markdown(contents).unwrap();
+        let rendered = render_markdown(
+            contents,
+            &Config::default(),
+            &Files::default(),
+            &Assets::default(),
+        )
+        .unwrap()
+        .body;
         assert_eq!(rendered, "<p>here’s a <em>thing</em></p>\n");
     }
 
     #[test]
     fn test_quotes() {
         let contents = "here's a \"thing\"";
-        let (_, rendered) = render_markdown(contents).unwrap();
+        let rendered = render_markdown(
+            contents,
+            &Config::default(),
+            &Files::default(),
+            &Assets::default(),
+        )
+        .unwrap()
+        .body;
         assert_eq!(rendered, "<p>here’s a “thing”</p>\n");
     }
 
     #[test]
     fn test_strikethrough() {
         let contents = "this is a ~thing~";
-        let (_, rendered) = render_markdown(contents).unwrap();
+        let rendered = render_markdown(
+            contents,
+            &Config::default(),
+            &Files::default(),
+            &Assets::default(),
+        )
+        .unwrap()
+        .body;
         assert_eq!(rendered, "<p>this is a <del>thing</del></p>\n");
     }
 
@@ -168,7 +1277,14 @@ more text
 
 [^1]: The thing I mentioned
 ";
-        let (_, rendered) = render_markdown(contents).unwrap();
+        let rendered = render_markdown(
+            contents,
+            &Config::default(),
+            &Files::default(),
+            &Assets::default(),
+        )
+        .unwrap()
+        .body;
         let expected = "<p>I mentioned<span>\
                         <label class=\"margin-toggle sidenote-number\" for=\"sn-1\"></label>\
                         <input class=\"margin-toggle\" id=\"sn-1\" type=\"checkbox\"/>\
@@ -0,0 +1,158 @@
+//! Smaller copies of the images in posts, for responsive `srcset`s.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use image::imageops::FilterType;
+use image::ImageFormat;
+
+use super::static_files;
+use crate::error::{Error, Result};
+
+/// Where the smaller copies of images go, mirroring the URLs of the originals.
+const VARIANTS_URL: &str = "/_images";
+
+/// An image that a post shows.
+#[derive(Debug, Default, PartialEq)]
+pub struct Image {
+    /// The size of the original, if it's a format we can read.
+    pub size: Option<(u32, u32)>,
+    /// Smaller copies of the image, narrowest first.
+    pub variants: Vec<Variant>,
+}
+
+/// A smaller copy of an image.
+#[derive(Debug, PartialEq)]
+pub struct Variant {
+    pub source: PathBuf,
+    pub url: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Image {
+    /// Look at the image at `source`, which is published at `url`, and work
+    /// out which of `widths` to make copies at.
+    ///
+    /// Only reads as much of the image as it needs to find its size.
+    pub fn inspect(source: &Path, url: &str, widths: &[u32]) -> Result<Image> {
+        let format = match ImageFormat::from_path(source) {
+            Ok(format) => format,
+            Err(_) => {
+                return Ok(Image {
+                    size: None,
+                    variants: Vec::new(),
+                })
+            }
+        };
+        let (width, height) = image::image_dimensions(source)
+            .map_err(|e| Error::Asset(source.to_owned(), format!("Cannot read image: {}", e)))?;
+        let mut variants = Vec::new();
+        // Resizing a GIF would lose its animation.
+        if format != ImageFormat::Gif {
+            let mut widths = widths.to_vec();
+            widths.sort_unstable();
+            widths.dedup();
+            for variant_width in widths.into_iter().filter(|w| *w < width) {
+                let variant_height =
+                    (u64::from(height) * u64::from(variant_width) / u64::from(width)).max(1);
+                variants.push(Variant {
+                    source: source.to_owned(),
+                    url: variant_url(url, variant_width),
+                    width: variant_width,
+                    height: variant_height as u32,
+                });
+            }
+        }
+        Ok(Image {
+            size: Some((width, height)),
+            variants,
+        })
+    }
+}
+
+impl Variant {
+    /// Write the copy to `dest`, unless it's already there and newer than
+    /// the original.
+    pub fn write(&self, dest: &Path) -> Result<()> {
+        if is_up_to_date(&self.source, dest) {
+            log::trace!("{} is up to date", self.url);
+            return Ok(());
+        }
+        let original = image::open(&self.source)
+            .map_err(|e| Error::Asset(self.source.clone(), format!("Cannot read image: {}", e)))?;
+        let resized = original.resize_exact(self.width, self.height, FilterType::Lanczos3);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).map_err(|e| Error::Io(parent.to_owned(), e))?;
+        }
+        static_files::remove_file(dest)?;
+        resized
+            .save(dest)
+            .map_err(|e| Error::Asset(dest.to_owned(), format!("Cannot write image: {}", e)))?;
+        log::debug!("Resized {} to {}px wide", self.source.display(), self.width);
+        Ok(())
+    }
+}
+
+fn is_up_to_date(source: &Path, dest: &Path) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified());
+    match (modified(source), modified(dest)) {
+        (Ok(source), Ok(dest)) => dest >= source,
+        _ => false,
+    }
+}
+
+/// The URL of the copy of the image at `url` that's `width` pixels wide.
+fn variant_url(url: &str, width: u32) -> String {
+    let file_start = url.rfind('/').map_or(0, |i| i + 1);
+    let (stem, extension) = match url[file_start..].rfind('.') {
+        Some(dot) => url.split_at(file_start + dot),
+        None => (url, ""),
+    };
+    format!("{}{}-{}w{}", VARIANTS_URL, stem, width, extension)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_variant_url() {
+        assert_eq!(
+            variant_url("/static/photo.jpg", 480),
+            "/_images/static/photo-480w.jpg"
+        );
+        assert_eq!(
+            variant_url("/2019/post/v1.2/plot", 960),
+            "/_images/2019/post/v1.2/plot-960w"
+        );
+    }
+
+    #[test]
+    fn test_inspect() {
+        let dir = TempDir::new("images");
+        let dir = dir.path();
+        let source = dir.join("wide.png");
+        image::RgbImage::new(1000, 500).save(&source).unwrap();
+        let image = Image::inspect(&source, "/static/wide.png", &[1920, 480, 960]);
+        let variant = Variant {
+            source: source.clone(),
+            url: String::from("/_images/static/wide-480w.png"),
+            width: 480,
+            height: 240,
+        };
+        let dest = dir.join("wide-480w.png");
+        variant.write(&dest).unwrap();
+        let written = image::image_dimensions(&dest);
+
+        let image = image.unwrap();
+        assert_eq!(image.size, Some((1000, 500)));
+        assert_eq!(
+            image.variants.iter().map(|v| v.width).collect::<Vec<_>>(),
+            [480, 960]
+        );
+        assert_eq!(image.variants[0], variant);
+        assert_eq!(written.unwrap(), (480, 240));
+    }
+}
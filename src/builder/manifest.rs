@@ -0,0 +1,439 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::iter;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use super::permalink::DIRECTORY_INDEX;
+
+/// A record of what the last build wrote to the output directory.
+///
+/// Lets a build find the pages that an earlier build wrote but that no longer
+/// have a post, even when permalinks put them in arbitrary places. Also
+/// remembers the URLs posts used to have, so we can redirect from them.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Manifest {
+    /// Maps the name of each post to its URL.
+    posts: BTreeMap<String, String>,
+    /// Maps the URLs posts used to have to the name of the post they should
+    /// redirect to.
+    redirects: BTreeMap<String, String>,
+    /// Maps the aliases in each post's front matter to the name of the post.
+    aliases: BTreeMap<String, String>,
+    /// Maps the URL of each standalone page to its source, relative to the
+    /// pages directory.
+    standalone: BTreeMap<String, String>,
+    /// Maps the URL of each file copied from a post's directory to the name
+    /// of the post.
+    assets: BTreeMap<String, String>,
+    /// Maps the URL of each resized copy of an image to the post or page
+    /// that first showed it. Posts and pages can show the same image.
+    images: BTreeMap<String, String>,
+    /// Maps the URL of each post to its name, so posts can be found by URL
+    /// without looking through them all.
+    #[serde(skip)]
+    post_names: HashMap<String, String>,
+}
+
+impl Manifest {
+    /// Load a manifest, if there is one.
+    pub fn load(path: &Path) -> io::Result<Option<Manifest>> {
+        match fs::read(path) {
+            Ok(bytes) => {
+                let mut manifest: Manifest = serde_json::from_slice(&bytes)?;
+                manifest.post_names = manifest
+                    .posts
+                    .iter()
+                    .map(|(name, url)| (url.clone(), name.clone()))
+                    .collect();
+                Ok(Some(manifest))
+            }
+            Err(err) => match err.kind() {
+                io::ErrorKind::NotFound => Ok(None),
+                _ => Err(err),
+            },
+        }
+    }
+
+    /// Work out what a build from before blake kept manifests wrote, from the
+    /// posts it left in `posts_dir`.
+    ///
+    /// Those builds wrote each post to `/posts/{name}.html`, and nothing else
+    /// that later builds might not.
+    pub fn scan_old_build(posts_dir: &Path) -> io::Result<Manifest> {
+        let mut manifest = Manifest::default();
+        let entries = match fs::read_dir(posts_dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(manifest),
+            Err(err) => return Err(err),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if !path.is_file() || path.extension().is_none_or(|ext| ext != "html") {
+                continue;
+            }
+            if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                manifest.add_post(name, &format!("/posts/{}.html", name));
+            }
+        }
+        Ok(manifest)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_vec_pretty(self)?;
+        fs::write(path, json)
+    }
+
+    /// Record that the post with the given name has the given URL.
+    ///
+    /// Returns the name of the post that already has the URL, if there is one.
+    pub fn add_post(&mut self, name: &str, url: &str) -> Option<String> {
+        if let Some(other) = self.owner(url) {
+            return Some(other);
+        }
+        self.posts.insert(name.to_owned(), url.to_owned());
+        self.post_names.insert(url.to_owned(), name.to_owned());
+        None
+    }
+
+    /// Record that the standalone page from the given source has the given URL.
+    ///
+    /// Returns the name of the post or page that already has the URL, if
+    /// there is one. Must be called after all the posts have been added.
+    pub fn add_standalone(&mut self, source: &str, url: &str) -> Option<String> {
+        if let Some(other) = self.owner(url) {
+            return Some(other);
+        }
+        self.standalone.insert(url.to_owned(), source.to_owned());
+        None
+    }
+
+    /// Record that a file belonging to the post with the given name was
+    /// copied to the given URL.
+    ///
+    /// Returns what already has the URL, if anything does.
+    pub fn add_asset(&mut self, url: &str, name: &str) -> Option<String> {
+        if let Some(other) = self.owner(url) {
+            return Some(other);
+        }
+        self.assets.insert(url.to_owned(), name.to_owned());
+        None
+    }
+
+    /// Record that a resized image shown by the given post or page was
+    /// written to the given URL.
+    ///
+    /// Returns what already has the URL, if anything other than the same
+    /// image does.
+    pub fn add_image(&mut self, url: &str, owner: &str) -> Option<String> {
+        if self.images.contains_key(url) {
+            return None;
+        }
+        if let Some(other) = self.owner(url) {
+            return Some(other);
+        }
+        self.images.insert(url.to_owned(), owner.to_owned());
+        None
+    }
+
+    /// Record that the given alias from the front matter of the post with the
+    /// given name should redirect to it.
+    ///
+    /// Returns what already has the URL, if anything other than the same
+    /// alias does. Must be called after all the posts and pages have been
+    /// added.
+    pub fn add_alias(&mut self, from: &str, name: &str) -> Option<String> {
+        if self.aliases.get(from).is_some_and(|other| other == name) {
+            return None;
+        }
+        if let Some(other) = self.owner(from) {
+            return Some(other);
+        }
+        self.aliases.insert(from.to_owned(), name.to_owned());
+        None
+    }
+
+    /// Keep redirecting from every URL the previous build had for a post
+    /// that's still around.
+    ///
+    /// Only the URLs posts moved from are carried over. Aliases come from
+    /// the front matter each time, so one taken out of it goes away. Must be
+    /// called after all the current posts and aliases have been added.
+    pub fn carry_over_redirects(&mut self, previous: &Manifest) {
+        let moved = previous
+            .posts
+            .iter()
+            .filter(|(name, old_url)| self.posts.get(*name).is_some_and(|url| url != *old_url));
+        let carried = moved
+            .map(|(name, old_url)| (old_url, name))
+            .chain(&previous.redirects)
+            .filter(|(from, name)| {
+                self.posts.contains_key(*name) && !self.aliases.contains_key(*from)
+            })
+            .map(|(from, name)| (from.clone(), name.clone()))
+            .collect::<Vec<_>>();
+        for (from, name) in carried {
+            self.redirects.entry(from).or_insert(name);
+        }
+    }
+
+    /// Pairs of URLs to redirect from and to, for aliases and then moved
+    /// posts.
+    ///
+    /// Skips redirects from URLs that are now used by a post, and redirects
+    /// to posts that no longer exist.
+    pub fn redirects(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.aliases
+            .iter()
+            .chain(&self.redirects)
+            .filter_map(move |(from, name)| match self.posts.get(name) {
+                Some(to) if !self.has_page_url(from) => Some((from.as_str(), to.as_str())),
+                _ => None,
+            })
+    }
+
+    /// URLs of pages in this manifest that aren't in the other one.
+    pub fn removed_pages<'a>(&'a self, current: &'a Manifest) -> impl Iterator<Item = &'a str> {
+        self.pages().filter(move |url| !current.has_page(url))
+    }
+
+    /// URLs of all the pages and files written for this manifest.
+    fn pages(&self) -> impl Iterator<Item = &str> {
+        self.posts
+            .values()
+            .chain(self.standalone.keys())
+            .chain(self.assets.keys())
+            .chain(self.images.keys())
+            .map(|url| url.as_str())
+            .chain(self.redirects().map(|(from, _)| from))
+    }
+
+    /// Is one of the pages or files written for this manifest at exactly
+    /// `url`?
+    fn has_page(&self, url: &str) -> bool {
+        self.post_names.contains_key(url)
+            || self.standalone.contains_key(url)
+            || self.assets.contains_key(url)
+            || self.images.contains_key(url)
+            || self
+                .aliases
+                .get(url)
+                .or_else(|| self.redirects.get(url))
+                .is_some_and(|name| self.posts.contains_key(name) && !self.has_page_url(url))
+    }
+
+    /// Is there a post, standalone page or file at `url`?
+    fn has_page_url(&self, url: &str) -> bool {
+        same_file_urls(url).any(|url| {
+            self.post_names.contains_key(&url)
+                || self.standalone.contains_key(&url)
+                || self.assets.contains_key(&url)
+                || self.images.contains_key(&url)
+        })
+    }
+
+    /// What is written to the same file as `url`, if anything: the name of a
+    /// post, the source of a standalone page, or one of their files.
+    fn owner(&self, url: &str) -> Option<String> {
+        let find = |urls: &BTreeMap<String, String>| {
+            same_file_urls(url).find_map(|url| urls.get(&url).cloned())
+        };
+        same_file_urls(url)
+            .find_map(|url| self.post_names.get(&url).cloned())
+            .or_else(|| find(&self.standalone))
+            .or_else(|| find(&self.assets).map(|name| format!("a file of {}", name)))
+            .or_else(|| find(&self.images).map(|owner| format!("an image in {}", owner)))
+            .or_else(|| find(&self.aliases).map(|name| format!("an alias of {}", name)))
+    }
+}
+
+/// `url`, and the other URL written to the same file, if there is one, as
+/// `/about/` and `/about/index.html` are.
+fn same_file_urls(url: &str) -> impl Iterator<Item = String> {
+    let other = match url.strip_suffix(DIRECTORY_INDEX) {
+        Some(dir) if dir.ends_with('/') => Some(dir.to_owned()),
+        _ if url.ends_with('/') => Some(format!("{}{}", url, DIRECTORY_INDEX)),
+        _ => None,
+    };
+    iter::once(url.to_owned()).chain(other)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_duplicate_url() {
+        let mut manifest = Manifest::default();
+        assert_eq!(manifest.add_post("first", "/posts/a.html"), None);
+        assert_eq!(
+            manifest.add_post("second", "/posts/a.html"),
+            Some(String::from("first"))
+        );
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = TempDir::new("manifest");
+        let path = dir.path().join("manifest.json");
+        let mut saved = Manifest::default();
+        saved.add_post("post", "/2019/post/");
+        saved.save(&path).unwrap();
+
+        let mut loaded = Manifest::load(&path).unwrap().unwrap();
+        let missing = Manifest::load(&dir.path().join("missing.json")).unwrap();
+
+        assert_eq!(
+            loaded.add_post("other", "/2019/post/index.html"),
+            Some(String::from("post"))
+        );
+        assert_eq!(loaded.removed_pages(&saved).count(), 0);
+        assert!(missing.is_none());
+    }
+
+    #[test]
+    fn test_asset_conflicts() {
+        let mut manifest = Manifest::default();
+        manifest.add_post("post", "/post/");
+        assert_eq!(manifest.add_asset("/post/data.csv", "post"), None);
+        assert_eq!(manifest.add_image("/_images/a-480w.png", "post"), None);
+        assert_eq!(manifest.add_image("/_images/a-480w.png", "about.md"), None);
+
+        assert_eq!(
+            manifest.add_asset("/post/index.html", "post"),
+            Some(String::from("post"))
+        );
+        assert_eq!(
+            manifest.add_post("other", "/post/data.csv"),
+            Some(String::from("a file of post"))
+        );
+        assert_eq!(
+            manifest.add_standalone("images.md", "/_images/a-480w.png"),
+            Some(String::from("an image in post"))
+        );
+        assert_eq!(
+            manifest.add_image("/post/data.csv", "about.md"),
+            Some(String::from("a file of post"))
+        );
+    }
+
+    #[test]
+    fn test_moved_posts_redirect() {
+        let mut previous = Manifest::default();
+        previous.add_post("post", "/posts/post.html");
+        let mut current = Manifest::default();
+        current.add_post("post", "/2019/11/post/");
+        current.carry_over_redirects(&previous);
+        let redirects = current.redirects().collect::<Vec<_>>();
+        assert_eq!(redirects, vec![("/posts/post.html", "/2019/11/post/")]);
+
+        let mut next = Manifest::default();
+        next.add_post("post", "/2019/post/");
+        next.carry_over_redirects(&current);
+        let redirects = next.redirects().collect::<Vec<_>>();
+        assert_eq!(
+            redirects,
+            vec![
+                ("/2019/11/post/", "/2019/post/"),
+                ("/posts/post.html", "/2019/post/")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_removed_aliases() {
+        let mut previous = Manifest::default();
+        previous.add_post("post", "/posts/post.html");
+        assert_eq!(previous.add_alias("/old.html", "post"), None);
+        let mut current = Manifest::default();
+        current.add_post("post", "/posts/post.html");
+        current.carry_over_redirects(&previous);
+        let removed = previous.removed_pages(&current).collect::<Vec<_>>();
+        assert_eq!(current.redirects().count(), 0);
+        assert_eq!(removed, vec!["/old.html"]);
+    }
+
+    #[test]
+    fn test_redirects_skip_posts() {
+        let mut manifest = Manifest::default();
+        manifest.add_post("post", "/posts/post.html");
+        manifest.add_post("other", "/posts/other.html");
+        assert_eq!(manifest.add_alias("/old.html", "deleted"), None);
+        assert_eq!(manifest.redirects().count(), 0);
+    }
+
+    #[test]
+    fn test_removed_pages() {
+        let mut previous = Manifest::default();
+        previous.add_post("kept", "/posts/kept.html");
+        previous.add_post("moved", "/posts/moved.html");
+        previous.add_post("deleted", "/posts/deleted.html");
+        let mut current = Manifest::default();
+        current.add_post("kept", "/posts/kept.html");
+        current.add_post("moved", "/2019/11/moved/");
+        let removed = previous.removed_pages(&current).collect::<Vec<_>>();
+        assert_eq!(removed, vec!["/posts/deleted.html", "/posts/moved.html"]);
+    }
+
+    #[test]
+    fn test_scan_old_build() {
+        let output = TempDir::new("old-build");
+        let posts_dir = output.path().join("posts");
+        fs::create_dir_all(posts_dir.join("post")).unwrap();
+        fs::write(posts_dir.join("kept.html"), "").unwrap();
+        fs::write(posts_dir.join("deleted.html"), "").unwrap();
+        fs::write(posts_dir.join("post/diagram.png"), "").unwrap();
+
+        let previous = Manifest::scan_old_build(&posts_dir).unwrap();
+        let missing = Manifest::scan_old_build(&output.path().join("missing")).unwrap();
+        let mut current = Manifest::default();
+        current.add_post("kept", "/posts/kept.html");
+        let removed = previous.removed_pages(&current).collect::<Vec<_>>();
+
+        assert_eq!(removed, vec!["/posts/deleted.html"]);
+        assert_eq!(missing.pages().count(), 0);
+    }
+
+    #[test]
+    fn test_standalone_pages() {
+        let mut previous = Manifest::default();
+        previous.add_post("post", "/posts/post.html");
+        assert_eq!(previous.add_standalone("about.md", "/about.html"), None);
+        assert_eq!(previous.add_standalone("now/index.md", "/now/"), None);
+        assert_eq!(
+            previous.add_standalone("posts/post.md", "/posts/post.html"),
+            Some(String::from("post"))
+        );
+        assert_eq!(
+            previous.add_alias("/about.html", "post"),
+            Some(String::from("about.md"))
+        );
+        assert_eq!(
+            previous.add_alias("/now/index.html", "post"),
+            Some(String::from("now/index.md"))
+        );
+        assert_eq!(previous.redirects().count(), 0);
+
+        let mut current = Manifest::default();
+        current.add_standalone("about.md", "/about.html");
+        let removed = previous.removed_pages(&current).collect::<Vec<_>>();
+        assert_eq!(removed, vec!["/posts/post.html", "/now/"]);
+    }
+
+    #[test]
+    fn test_removed_assets() {
+        let mut previous = Manifest::default();
+        previous.add_post("post", "/posts/post.html");
+        assert_eq!(previous.add_asset("/posts/post/kept.png", "post"), None);
+        assert_eq!(previous.add_asset("/posts/post/deleted.png", "post"), None);
+        let mut current = Manifest::default();
+        current.add_post("post", "/posts/post.html");
+        assert_eq!(current.add_asset("/posts/post/kept.png", "post"), None);
+        let removed = previous.removed_pages(&current).collect::<Vec<_>>();
+        assert_eq!(removed, vec!["/posts/post/deleted.png"]);
+    }
+}
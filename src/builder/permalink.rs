@@ -0,0 +1,150 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use chrono::prelude::*;
+
+use crate::posts;
+
+/// The file a directory URL is served from.
+pub const DIRECTORY_INDEX: &str = "index.html";
+
+/// Where a post lives on the site.
+///
+/// Permalinks are made by filling in a template, like `/posts/{name}.html`
+/// or `/{year}/{month}/{slug}/`, with details of the post. The URL and the
+/// file the post is written to both come from the permalink, so they always
+/// agree.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Permalink {
+    url: String,
+}
+
+impl Permalink {
+    pub fn new(template: &str, post: &posts::Post) -> Result<Permalink, Error> {
+        if !template.starts_with('/') {
+            return Err(Error::NotAbsolute(template.to_owned()));
+        }
+        let date = post.date();
+        let mut url = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            url.push_str(&rest[..start]);
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| Error::Unterminated(template.to_owned()))?;
+            let placeholder = &rest[start + 1..start + end];
+            let value = match placeholder {
+                "year" => format!("{:04}", date.year()),
+                "month" => format!("{:02}", date.month()),
+                "day" => format!("{:02}", date.day()),
+                "hour" => format!("{:02}", date.hour()),
+                "minute" => format!("{:02}", date.minute()),
+                "name" => post.name().to_owned(),
+                "slug" => post.slug().to_owned(),
+                _ => return Err(Error::UnknownPlaceholder(placeholder.to_owned())),
+            };
+            url.push_str(&value);
+            rest = &rest[start + end + 1..];
+        }
+        url.push_str(rest);
+        Ok(Permalink { url })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Where a post's own files go: next to the page if it's the index of a
+    /// directory, or otherwise in a directory named after the page.
+    pub fn bundle_url(&self) -> String {
+        if self.url.ends_with('/') {
+            return self.url.clone();
+        }
+        let file_start = self.url.rfind('/').map_or(0, |i| i + 1);
+        let stem = match self.url[file_start..].rfind('.') {
+            Some(dot) => &self.url[..file_start + dot],
+            None => &self.url,
+        };
+        format!("{}/", stem)
+    }
+}
+
+/// Turn a site URL into a file path relative to the output directory.
+///
+/// URLs that end in a slash are directories, served from their index page.
+pub fn url_to_path(url: &str) -> PathBuf {
+    let mut path = PathBuf::from(url.trim_start_matches('/'));
+    if url.ends_with('/') {
+        path.push(DIRECTORY_INDEX);
+    }
+    path
+}
+
+/// Errors that can be caused by a bad permalink template.
+#[derive(Debug)]
+pub enum Error {
+    NotAbsolute(String),
+    Unterminated(String),
+    UnknownPlaceholder(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::NotAbsolute(template) => {
+                write!(f, "Permalink must start with '/': {}", template)
+            }
+            Error::Unterminated(template) => {
+                write!(f, "Unterminated placeholder in permalink: {}", template)
+            }
+            Error::UnknownPlaceholder(placeholder) => {
+                write!(f, "Unknown placeholder in permalink: {{{}}}", placeholder)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::FilenamePattern;
+    use chrono_tz::Tz;
+
+    fn post(name: &str) -> posts::Post {
+        let patterns = [FilenamePattern::DateTime, FilenamePattern::DateSlug];
+        posts::Post::new(PathBuf::from(name).with_extension("md"), Tz::UTC, &patterns).unwrap()
+    }
+
+    #[test]
+    fn test_flat_permalink() {
+        let permalink = Permalink::new("/posts/{name}.html", &post("2019-11-25-10:30")).unwrap();
+        assert_eq!(permalink.url(), "/posts/2019-11-25-10:30.html");
+        assert_eq!(
+            url_to_path(permalink.url()),
+            PathBuf::from("posts/2019-11-25-10:30.html")
+        );
+        assert_eq!(permalink.bundle_url(), "/posts/2019-11-25-10:30/");
+    }
+
+    #[test]
+    fn test_pretty_permalink() {
+        let permalink =
+            Permalink::new("/{year}/{month}/{slug}/", &post("2019-01-05-my-title")).unwrap();
+        assert_eq!(permalink.url(), "/2019/01/my-title/");
+        assert_eq!(
+            url_to_path(permalink.url()),
+            PathBuf::from("2019/01/my-title/index.html")
+        );
+        assert_eq!(permalink.bundle_url(), "/2019/01/my-title/");
+    }
+
+    #[test]
+    fn test_bad_permalinks() {
+        let post = post("2019-11-25-10:30");
+        assert!(Permalink::new("posts/{name}.html", &post).is_err());
+        assert!(Permalink::new("/posts/{name.html", &post).is_err());
+        assert!(Permalink::new("/posts/{title}.html", &post).is_err());
+    }
+}
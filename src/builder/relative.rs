@@ -0,0 +1,149 @@
+/// Attribute openings whose values are URLs.
+const URL_ATTRIBUTES: &[&str] = &[" href=\"", " src=\"", " srcset=\"", "content=\"0; url="];
+
+/// The attribute whose value is a list of URLs, each with a size.
+const SRCSET_ATTRIBUTE: &str = " srcset=\"";
+
+/// The file a directory URL is served from.
+const DIRECTORY_INDEX: &str = "index.html";
+
+/// Rewrite the links in a page so that they're relative to the page itself.
+///
+/// Links to pages on the site, which start with `/` followed by the site's
+/// path `prefix`, become relative, so the site works when opened straight
+/// from disk. Links to directories get an explicit `index.html`, since there's
+/// no web server to find it. Everything else is left alone.
+pub fn relativize(html: &str, page_url: &str, prefix: &str) -> String {
+    let mut output = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some((start, attribute)) = find_attribute(rest) {
+        let value_start = start + attribute.len();
+        output.push_str(&rest[..value_start]);
+        rest = &rest[value_start..];
+        let value_end = rest.find('"').unwrap_or(rest.len());
+        let value = rest[..value_end].replace("&#x2F;", "/");
+        if attribute == SRCSET_ATTRIBUTE {
+            output.push_str(&relativize_srcset(&value, page_url, prefix));
+        } else {
+            match site_path(&value, prefix) {
+                Some(path) => output.push_str(&relative_url(page_url, path)),
+                None => output.push_str(&rest[..value_end]),
+            }
+        }
+        rest = &rest[value_end..];
+    }
+    output.push_str(rest);
+    output
+}
+
+/// Rewrite each URL in a `srcset`, like `/a-480w.png 480w, /a.png 1000w`.
+fn relativize_srcset(srcset: &str, page_url: &str, prefix: &str) -> String {
+    srcset
+        .split(',')
+        .map(|candidate| {
+            let candidate = candidate.trim();
+            let (url, size) = candidate.split_at(candidate.find(' ').unwrap_or(candidate.len()));
+            match site_path(url, prefix) {
+                Some(path) => format!("{}{}", relative_url(page_url, path), size),
+                None => candidate.to_owned(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Find the earliest URL attribute in `html`.
+fn find_attribute(html: &str) -> Option<(usize, &'static str)> {
+    URL_ATTRIBUTES
+        .iter()
+        .filter_map(|attribute| html.find(attribute).map(|start| (start, *attribute)))
+        .min()
+}
+
+/// If `url` points to a page on the site, return its path within the site.
+fn site_path<'a>(url: &'a str, prefix: &str) -> Option<&'a str> {
+    if !url.starts_with('/') || url.starts_with("//") {
+        return None;
+    }
+    let path = url.strip_prefix(prefix)?;
+    if path.starts_with('/') {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// The URL of `target`, relative to the page at `page_url`.
+///
+/// Both URLs must be paths within the site, starting with `/`.
+pub fn relative_url(page_url: &str, target: &str) -> String {
+    let suffix_start = target.find(['#', '?']).unwrap_or(target.len());
+    let (target, suffix) = target.split_at(suffix_start);
+    let mut target = target.to_owned();
+    if target.ends_with('/') {
+        target.push_str(DIRECTORY_INDEX);
+    }
+    let page_dir = page_url[..page_url.rfind('/').map_or(0, |i| i + 1)]
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>();
+    let target_parts = target
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>();
+    let target_dir = &target_parts[..target_parts.len() - 1];
+    let common = page_dir
+        .iter()
+        .zip(target_dir)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut relative = "../".repeat(page_dir.len() - common);
+    relative.push_str(&target_parts[common..].join("/"));
+    relative.push_str(suffix);
+    relative
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_url() {
+        assert_eq!(relative_url("/", "/static/tufte.css"), "static/tufte.css");
+        assert_eq!(relative_url("/index.html", "/"), "index.html");
+        assert_eq!(
+            relative_url("/posts/a.html", "/static/tufte.css"),
+            "../static/tufte.css"
+        );
+        assert_eq!(relative_url("/posts/a.html", "/posts/b.html"), "b.html");
+        assert_eq!(
+            relative_url("/2019/11/a/", "/2019/12/b/#sn-1"),
+            "../../12/b/index.html#sn-1"
+        );
+        assert_eq!(relative_url("/2019/11/a/", "/"), "../../../index.html");
+    }
+
+    #[test]
+    fn test_relativize() {
+        let html = "<link href=\"&#x2F;notebook&#x2F;static&#x2F;tufte.css\"/>\
+                    <a href=\"/notebook/posts/b.html\">b</a>\
+                    <a href=\"https://example.org/notebook/\">home</a>\
+                    <a href=\"#sn-1\">note</a>\
+                    <img src=\"/elsewhere/x.png\"/>\
+                    <img srcset=\"/notebook/_images/a-480w.png 480w, /notebook/a.png 1000w\"/>";
+        let expected = "<link href=\"../static/tufte.css\"/>\
+                        <a href=\"b.html\">b</a>\
+                        <a href=\"https://example.org/notebook/\">home</a>\
+                        <a href=\"#sn-1\">note</a>\
+                        <img src=\"/elsewhere/x.png\"/>\
+                        <img srcset=\"../_images/a-480w.png 480w, ../a.png 1000w\"/>";
+        assert_eq!(relativize(html, "/posts/a.html", "/notebook"), expected);
+    }
+
+    #[test]
+    fn test_relativize_redirect() {
+        let html = "<meta http-equiv=\"refresh\" content=\"0; url=/posts/b.html\"/>";
+        let expected = "<meta http-equiv=\"refresh\" content=\"0; url=../b.html\"/>";
+        assert_eq!(relativize(html, "/posts/old/a.html", ""), expected);
+    }
+}
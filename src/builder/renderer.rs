@@ -0,0 +1,231 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono_tz::Tz;
+use tera::Tera;
+
+use super::assets::Assets;
+use super::functions;
+use super::html;
+use super::site::Site;
+use super::static_files;
+use super::urls::Urls;
+use crate::config::{Config, NavLink};
+use crate::error::{Error, Result};
+use crate::theme::{self, Theme};
+
+/// Renders pages of the site from templates.
+///
+/// Templates in the site's templates directory take the place of the theme's
+/// templates with the same name. Any templates the theme doesn't have come
+/// from the default theme.
+pub struct Renderer {
+    stylesheets: Vec<String>,
+    assets: Assets,
+    site: Site,
+    urls: Urls,
+    tera: Tera,
+}
+
+impl Renderer {
+    pub fn new(config: &Config, theme: &Theme) -> Result<Renderer> {
+        let urls = Urls::from_config(config);
+        let tera = load_templates(
+            config.templates_dir.as_deref(),
+            theme,
+            &urls,
+            config.timezone,
+        )?;
+        let mut stylesheets = theme.stylesheets().to_vec();
+        stylesheets.extend(config.stylesheets.iter().cloned());
+        Ok(Renderer {
+            stylesheets,
+            assets: Assets::default(),
+            site: Site::new(config),
+            urls,
+            tera,
+        })
+    }
+
+    /// Start again from `config` and `theme`, which may have changed since
+    /// the last build, loading the templates again to pick up any changes.
+    ///
+    /// If the templates can't be loaded, the renderer is left as it was.
+    /// Either way, the posts and assets need to be set again.
+    pub fn reload(&mut self, config: &Config, theme: &Theme) -> Result<()> {
+        *self = Renderer::new(config, theme)?;
+        Ok(())
+    }
+
+    /// Let templates look up the posts, given all of them, newest first.
+    pub fn set_posts(&mut self, posts: &[html::Post]) -> tera::Result<()> {
+        functions::register_posts(&mut self.tera, &self.urls, posts)
+    }
+
+    /// Point templates at the hashed names of static files.
+    pub fn set_assets(&mut self, assets: Assets) {
+        functions::register_assets(&mut self.tera, &self.urls, &assets);
+        self.assets = assets;
+    }
+
+    /// Let templates list the standalone pages.
+    pub fn set_pages(&mut self, pages: &[html::Page]) {
+        let links = pages
+            .iter()
+            .map(|page| NavLink {
+                title: match page.title() {
+                    Some(title) => title.text().to_owned(),
+                    None => page.url().to_owned(),
+                },
+                url: page.url().to_owned(),
+            })
+            .collect();
+        self.site.set_pages(links);
+    }
+
+    pub fn urls(&self) -> &Urls {
+        &self.urls
+    }
+
+    /// A context for rendering a template, with the things every page needs.
+    pub fn context(&self) -> tera::Context {
+        let mut context = tera::Context::new();
+        context.insert("site", &self.site);
+        let stylesheets = self
+            .stylesheets
+            .iter()
+            .map(|stylesheet| self.assets.url(stylesheet))
+            .collect::<Vec<_>>();
+        context.insert("stylesheets", &stylesheets);
+        context
+    }
+
+    pub fn render(&self, template: &str, context: &tera::Context) -> tera::Result<String> {
+        self.tera.render(template, context)
+    }
+
+    /// Render the page at `url` and write it to `dest_file`, unless it's
+    /// already there.
+    ///
+    /// Returns whether the page changed.
+    pub fn write_page(
+        &self,
+        template: &str,
+        context: &tera::Context,
+        url: &str,
+        dest_file: &Path,
+    ) -> Result<bool> {
+        let rendered = self
+            .render(template, context)
+            .map_err(|e| Error::Template(dest_file.to_owned(), e))?;
+        let changed =
+            static_files::write_if_changed(self.urls.page(url, rendered).as_bytes(), dest_file)?;
+        if changed {
+            log::debug!("Wrote {}", url);
+        } else {
+            log::trace!("{} is unchanged", url);
+        }
+        Ok(changed)
+    }
+}
+
+/// Load the site's templates from `templates_dir`, falling back to the
+/// theme's, and then to the default theme's.
+fn load_templates(
+    templates_dir: Option<&Path>,
+    theme: &Theme,
+    urls: &Urls,
+    timezone: Tz,
+) -> Result<Tera> {
+    let mut templates = Vec::new();
+    if let Some(templates_dir) = templates_dir {
+        if !templates_dir.is_dir() {
+            return Err(Error::Io(
+                templates_dir.to_owned(),
+                io::Error::new(io::ErrorKind::NotFound, "No templates directory"),
+            ));
+        }
+        templates = theme::read_templates(templates_dir)?;
+    }
+    let mut fallbacks = theme.templates()?;
+    if theme.name() != theme::DEFAULT_THEME {
+        fallbacks.extend(Theme::find(theme::DEFAULT_THEME, None)?.templates()?);
+    }
+    for (name, contents) in fallbacks {
+        if !templates.iter().any(|(existing, _)| *existing == name) {
+            templates.push((name, contents));
+        }
+    }
+    let mut tera = Tera::default();
+    tera.add_raw_templates(
+        templates
+            .iter()
+            .map(|(name, contents)| (name.as_str(), contents.as_str()))
+            .collect(),
+    )
+    .map_err(|e| {
+        let dir = templates_dir.map_or_else(|| PathBuf::from(theme.location()), Path::to_owned);
+        Error::Template(dir, e)
+    })?;
+    functions::register(&mut tera, urls, timezone);
+    Ok(tera)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::fs;
+
+    fn render_redirect(config: &Config) -> String {
+        let theme = Theme::find(&config.theme, None).unwrap();
+        let renderer = Renderer::new(config, &theme).unwrap();
+        let mut context = renderer.context();
+        context.insert("target", "/posts/foo.html");
+        renderer.render("redirect.html", &context).unwrap()
+    }
+
+    #[test]
+    fn test_builtin_templates() {
+        let config = Config::default();
+        assert!(render_redirect(&config).contains("url=&#x2F;posts&#x2F;foo.html"));
+    }
+
+    #[test]
+    fn test_site_templates_override_builtin() {
+        let templates_dir = TempDir::new("site-templates");
+        let templates_dir = templates_dir.path();
+        fs::write(
+            templates_dir.join("redirect.html"),
+            "Moved to {{ target | safe }}",
+        )
+        .unwrap();
+        let config = Config {
+            templates_dir: Some(templates_dir.to_owned()),
+            ..Config::default()
+        };
+        let rendered = render_redirect(&config);
+        assert_eq!(rendered, "Moved to /posts/foo.html");
+    }
+
+    #[test]
+    fn test_reload() {
+        let templates_dir = TempDir::new("reload-templates");
+        let templates_dir = templates_dir.path();
+        fs::write(templates_dir.join("redirect.html"), "{{ site.title }}").unwrap();
+        let mut config = Config {
+            templates_dir: Some(templates_dir.to_owned()),
+            ..Config::default()
+        };
+        let theme = Theme::find(&config.theme, None).unwrap();
+        let mut renderer = Renderer::new(&config, &theme).unwrap();
+        config.site.title = String::from("Renamed");
+        fs::write(templates_dir.join("redirect.html"), "{{ site.title }}!").unwrap();
+
+        renderer.reload(&config, &theme).unwrap();
+        let rendered = renderer
+            .render("redirect.html", &renderer.context())
+            .unwrap();
+        assert_eq!(rendered, "Renamed!");
+    }
+}
@@ -1,8 +1,6 @@
 use std::collections::HashMap;
-use std::error::Error;
 use std::string::FromUtf8Error;
 
-use comrak;
 use comrak::nodes::{AstNode, NodeValue};
 use comrak::{Arena, ComrakOptions};
 
@@ -10,7 +8,7 @@ pub fn render<'a>(
     arena: &'a Arena<AstNode<'a>>,
     root: &'a AstNode<'a>,
     options: &ComrakOptions,
-) -> Result<(), Box<dyn Error>> {
+) -> Result<(), String> {
     // Algorithm
     // - Find all the NodeValue::FootnoteDefinitions
     // - Render them
@@ -39,6 +37,7 @@ pub fn render<'a>(
     }
 
     replace_footnote_references(root, &rendered_footnotes);
+    log::trace!("Rendered {} sidenotes", rendered_footnotes.len());
     Ok(())
 }
 
@@ -57,21 +56,28 @@ fn find_footnote_definitions<'a>(root: &'a AstNode<'a>) -> HashMap<Vec<u8>, &'a
 /// Render a footnote definition as a sidenote.
 fn render_footnode_definition_as_sidenote<'a>(
     arena: &'a Arena<AstNode<'a>>,
-    tag: &Vec<u8>,
+    tag: &[u8],
     node: &'a AstNode<'a>,
     options: &ComrakOptions,
-) -> Result<NodeValue, Box<dyn Error>> {
+) -> Result<NodeValue, String> {
     let mut html = vec![];
     let document = arena.alloc(AstNode::from(NodeValue::Document));
-    for (i, child) in node.children().enumerate() {
-        assert!(i < 1, "Footnote definitions have one child, a paragraph.");
+    // Sidenotes sit inside the paragraph that refers to them, so they can
+    // only have one paragraph of their own.
+    if node.children().count() > 1 {
+        return Err(format!(
+            "Sidenote [^{}] has more than one paragraph",
+            String::from_utf8_lossy(tag)
+        ));
+    }
+    for child in node.children() {
         for grandchild in child.children() {
             grandchild.detach();
             document.append(grandchild);
         }
     }
-    comrak::format_html(document, &options, &mut html)?;
-    let sidenote = render_sidenote_html(tag.clone(), html)?;
+    comrak::format_html(document, options, &mut html).map_err(|e| e.to_string())?;
+    let sidenote = render_sidenote_html(tag.to_vec(), html).map_err(|e| e.to_string())?;
     Ok(NodeValue::HtmlInline(sidenote))
 }
 
@@ -108,14 +114,34 @@ fn render_sidenote_html(name: Vec<u8>, html: Vec<u8>) -> Result<Vec<u8>, FromUtf
     let name = String::from_utf8(name)?;
     let html = String::from_utf8(html)?;
     let output = format!(
-        "<span><label class=\"margin-toggle sidenote-number\" for=\"sn-{}\"></label>\
-         <input class=\"margin-toggle\" id=\"sn-{}\" type=\"checkbox\"/>\
-         <span class=\"sidenote\">{}</span></span>",
-        name, name, html
+        "<span>{}<span class=\"sidenote\">{}</span></span>",
+        margin_toggle("margin-toggle sidenote-number", "", &format!("sn-{}", name)),
+        html
     );
     Ok(output.into_bytes())
 }
 
+/// Create HTML for a margin note, which is like a sidenote without a number.
+///
+/// On narrow screens, margin notes are hidden behind a toggle, ⊕, that
+/// works the same way as a sidenote's number.
+pub fn render_margin_note_html(id: &str, html: &str) -> String {
+    format!(
+        "<span>{}<span class=\"marginnote\">{}</span></span>",
+        margin_toggle("margin-toggle", "&#8853;", id),
+        html
+    )
+}
+
+/// The label and dummy checkbox that show and hide a side or margin note.
+fn margin_toggle(label_class: &str, label: &str, id: &str) -> String {
+    format!(
+        "<label class=\"{}\" for=\"{}\">{}</label>\
+         <input class=\"margin-toggle\" id=\"{}\" type=\"checkbox\"/>",
+        label_class, id, label, id
+    )
+}
+
 fn replace_footnote_references<'a>(
     root: &'a AstNode<'a>,
     footnotes: &HashMap<&Vec<u8>, NodeValue>,
@@ -174,4 +200,22 @@ mod tests {
             _ => panic!("Unexpected value"),
         }
     }
+
+    #[test]
+    fn test_sidenote_with_paragraphs() {
+        let arena = comrak::Arena::new();
+        let options = ComrakOptions {
+            ext_footnotes: true,
+            ..ComrakOptions::default()
+        };
+        let contents = "I mentioned[^1] a thing.
+
+[^1]: One paragraph.
+
+    And another.
+";
+        let root = comrak::parse_document(&arena, contents, &options);
+        let error = render(&arena, root, &options).unwrap_err();
+        assert_eq!(error, "Sidenote [^1] has more than one paragraph");
+    }
 }
@@ -0,0 +1,49 @@
+use chrono::prelude::*;
+use chrono_tz::Tz;
+use serde::Serialize;
+
+use crate::config::{Config, NavLink};
+
+/// What templates know about the site as a whole, as `site`.
+#[derive(Debug, Serialize)]
+pub struct Site {
+    title: String,
+    subtitle: Option<String>,
+    author: Option<String>,
+    base_url: String,
+    nav: Vec<NavLink>,
+    /// The standalone pages, for navigation.
+    pages: Vec<NavLink>,
+    footer: Option<String>,
+    /// When the site was built, as an RFC 3339 timestamp in the site's timezone.
+    build_time: String,
+    /// The version of blake that built the site.
+    version: &'static str,
+    extra: toml::value::Table,
+}
+
+impl Site {
+    pub fn new(config: &Config) -> Site {
+        let site = &config.site;
+        Site {
+            title: site.title.clone(),
+            subtitle: site.subtitle.clone(),
+            author: site.author.clone(),
+            base_url: config.base_url.clone(),
+            nav: site.nav.clone(),
+            pages: Vec::new(),
+            footer: site.footer.clone(),
+            build_time: now(config.timezone),
+            version: env!("CARGO_PKG_VERSION"),
+            extra: site.extra.clone(),
+        }
+    }
+
+    pub fn set_pages(&mut self, pages: Vec<NavLink>) {
+        self.pages = pages;
+    }
+}
+
+fn now(timezone: Tz) -> String {
+    Utc::now().with_timezone(&timezone).to_rfc3339()
+}
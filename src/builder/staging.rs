@@ -0,0 +1,252 @@
+//! Building the site next to the live one, and swapping it in when it's done,
+//! so a failed build never leaves a mix of old and new pages.
+//!
+//! Each build goes in a numbered directory of its own, in `site.builds` for a
+//! site in `site`, and `site` itself is a symbolic link to the live build.
+//! Pointing the link at a new build is a single rename, so there's never a
+//! moment without a site, or with half of one.
+
+use std::fs;
+use std::io;
+#[cfg(unix)]
+use std::os::unix::fs::symlink;
+#[cfg(windows)]
+use std::os::windows::fs::symlink_dir as symlink;
+use std::path::{Path, PathBuf};
+
+use super::static_files;
+use super::OutputPath;
+use crate::error::{Error, Result};
+
+/// The output directory, which links to the live build, the directory the
+/// builds are kept in, and the link to the build before the live one.
+#[derive(Debug)]
+pub struct Staging {
+    output_dir: PathBuf,
+    builds_dir: PathBuf,
+    previous_dir: PathBuf,
+}
+
+impl Staging {
+    /// The staging directories for `output_dir`, e.g. `site.builds` and
+    /// `site.previous` for `site`.
+    pub fn new(output_dir: &Path) -> Staging {
+        let name = output_dir.file_name().map_or_else(
+            || String::from("output"),
+            |name| name.to_string_lossy().into_owned(),
+        );
+        Staging {
+            output_dir: output_dir.to_owned(),
+            builds_dir: output_dir.with_file_name(format!("{}.builds", name)),
+            previous_dir: output_dir.with_file_name(format!("{}.previous", name)),
+        }
+    }
+
+    /// Start a build, in a new directory with hard links to every file in
+    /// the current build, so that anything that's already up to date can be
+    /// left alone without being copied.
+    ///
+    /// Anything left over from a build that failed is thrown away. A site
+    /// built before blake kept its builds apart is moved into the builds
+    /// directory first.
+    pub fn start(&self) -> Result<OutputPath> {
+        fs::create_dir_all(&self.builds_dir).map_err(|e| Error::Io(self.builds_dir.clone(), e))?;
+        self.adopt(&self.output_dir)?;
+        self.adopt(&self.previous_dir)?;
+        self.remove_unused()?;
+        let path = self.builds_dir.join(self.next_build()?);
+        match self.build(&self.output_dir) {
+            Some(current) => link_dir(&self.builds_dir.join(current), &path)?,
+            None => fs::create_dir(&path).map_err(|e| Error::Io(path.clone(), e))?,
+        }
+        Ok(OutputPath { path })
+    }
+
+    /// Make the finished build in `output` the live one, and keep the current
+    /// one to roll back to.
+    pub fn finish(&self, output: &OutputPath) -> Result<()> {
+        let finished = PathBuf::from(
+            output
+                .path
+                .file_name()
+                .expect("Builds are in directories of their own."),
+        );
+        if let Some(current) = self.build(&self.output_dir) {
+            self.point(&self.previous_dir, &current)?;
+        }
+        self.point(&self.output_dir, &finished)?;
+        self.remove_unused()
+    }
+
+    /// Put the previous build back in place, and keep the current one as the
+    /// previous build, so rolling back again undoes the roll back.
+    pub fn rollback(&self) -> Result<()> {
+        let previous = self.build(&self.previous_dir).ok_or_else(|| {
+            Error::Io(
+                self.previous_dir.clone(),
+                io::Error::new(io::ErrorKind::NotFound, "No previous build to roll back to"),
+            )
+        })?;
+        if let Some(current) = self.build(&self.output_dir) {
+            self.point(&self.previous_dir, &current)?;
+        }
+        self.point(&self.output_dir, &previous)
+    }
+
+    /// The name of the build that `link` points to, if it's there.
+    fn build(&self, link: &Path) -> Option<PathBuf> {
+        let target = fs::read_link(link).ok()?;
+        let name = PathBuf::from(target.file_name()?);
+        if self.builds_dir.join(&name).is_dir() {
+            Some(name)
+        } else {
+            None
+        }
+    }
+
+    /// Point `link` at the build called `name`.
+    ///
+    /// The new link is made in the builds directory and renamed over the old
+    /// one, which replaces it in one step.
+    fn point(&self, link: &Path, name: &Path) -> Result<()> {
+        let builds = self
+            .builds_dir
+            .file_name()
+            .expect("The builds directory has a name.");
+        let new_link = self.builds_dir.join(".link");
+        static_files::remove_file(&new_link)?;
+        symlink(Path::new(builds).join(name), &new_link)
+            .map_err(|e| Error::Io(new_link.clone(), e))?;
+        fs::rename(&new_link, link).map_err(|e| Error::Io(link.to_owned(), e))
+    }
+
+    /// Move a directory that isn't yet a link to a build into the builds
+    /// directory, and link to it instead.
+    fn adopt(&self, dir: &Path) -> Result<()> {
+        match fs::symlink_metadata(dir) {
+            Ok(metadata) if metadata.is_dir() => {}
+            _ => return Ok(()),
+        }
+        let name = self.next_build()?;
+        let build = self.builds_dir.join(&name);
+        fs::rename(dir, &build).map_err(|e| Error::Io(dir.to_owned(), e))?;
+        self.point(dir, &name)
+    }
+
+    /// A name for a new build, one more than the highest so far.
+    fn next_build(&self) -> Result<PathBuf> {
+        let last = self
+            .builds()?
+            .iter()
+            .filter_map(|name| name.to_str()?.parse::<u64>().ok())
+            .max();
+        Ok(PathBuf::from(last.map_or(1, |last| last + 1).to_string()))
+    }
+
+    /// The names of all the builds.
+    fn builds(&self) -> Result<Vec<PathBuf>> {
+        let io_error = |e| Error::Io(self.builds_dir.clone(), e);
+        let mut builds = Vec::new();
+        for entry in fs::read_dir(&self.builds_dir).map_err(io_error)? {
+            let entry = entry.map_err(io_error)?;
+            if entry.file_type().map_err(io_error)?.is_dir() {
+                builds.push(PathBuf::from(entry.file_name()));
+            }
+        }
+        Ok(builds)
+    }
+
+    /// Remove every build except the current and previous ones.
+    fn remove_unused(&self) -> Result<()> {
+        let keep = [self.build(&self.output_dir), self.build(&self.previous_dir)];
+        for name in self.builds()? {
+            if !keep.contains(&Some(name.clone())) {
+                let dir = self.builds_dir.join(&name);
+                fs::remove_dir_all(&dir).map_err(|e| Error::Io(dir, e))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Make `dest` with the same files as `source`, as hard links to them.
+///
+/// Falls back to copying, keeping modification times, where the file system
+/// can't link them.
+fn link_dir(source: &Path, dest: &Path) -> Result<()> {
+    fs::create_dir_all(dest).map_err(|e| Error::Io(dest.to_owned(), e))?;
+    let io_error = |e| Error::Io(source.to_owned(), e);
+    for entry in fs::read_dir(source).map_err(io_error)? {
+        let entry = entry.map_err(io_error)?;
+        let path = entry.path();
+        let dest = dest.join(entry.file_name());
+        if entry.file_type().map_err(io_error)?.is_dir() {
+            link_dir(&path, &dest)?;
+        } else if fs::hard_link(&path, &dest).is_err() {
+            static_files::copy_if_changed(&path, &dest)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    #[cfg(unix)]
+    use std::os::unix::fs::MetadataExt;
+
+    fn build(staging: &Staging, contents: &str) {
+        let output = staging.start().unwrap();
+        static_files::write_if_changed(contents.as_bytes(), &output.index()).unwrap();
+        staging.finish(&output).unwrap();
+    }
+
+    #[test]
+    fn test_staging() {
+        let root = TempDir::new("staging");
+        let output_dir = root.path().join("site");
+        let staging = Staging::new(&output_dir);
+        let index = output_dir.join("index.html");
+        let read_index = || fs::read_to_string(&index).unwrap();
+        fs::create_dir(&output_dir).unwrap();
+        fs::write(&index, "zeroth").unwrap();
+        fs::write(output_dir.join("about.html"), "about").unwrap();
+
+        let no_previous = staging.rollback();
+        build(&staging, "first");
+        let is_link = fs::symlink_metadata(&output_dir)
+            .unwrap()
+            .file_type()
+            .is_symlink();
+        build(&staging, "second");
+        let built = read_index();
+        staging.rollback().unwrap();
+        let rolled_back = read_index();
+        staging.rollback().unwrap();
+        let rolled_forward = read_index();
+        let failed = staging.start().unwrap();
+        static_files::write_if_changed(b"broken", &failed.index()).unwrap();
+        let during_failed = read_index();
+        let previous = fs::read_to_string(root.path().join("site.previous/index.html")).unwrap();
+        let builds = staging.builds().unwrap().len();
+
+        assert!(no_previous.is_err());
+        assert!(is_link);
+        assert_eq!(built, "second");
+        assert_eq!(rolled_back, "first");
+        assert_eq!(rolled_forward, "second");
+        assert_eq!(during_failed, "second");
+        assert_eq!(previous, "first");
+        assert_eq!(builds, 3);
+        #[cfg(unix)]
+        {
+            let inode = |path: PathBuf| fs::metadata(path).unwrap().ino();
+            assert_eq!(
+                inode(output_dir.join("about.html")),
+                inode(failed.path.join("about.html"))
+            );
+            assert_ne!(inode(index), inode(failed.index()));
+        }
+    }
+}
@@ -0,0 +1,297 @@
+//! The site's static files, and keeping the published copy of them in step.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+use crate::theme::{StaticFile, Theme};
+use crate::walk;
+
+/// The static files of a site: the theme's, and then the site's own, which
+/// replace any of the theme's with the same name.
+#[derive(Debug, Default)]
+pub struct StaticFiles {
+    /// The contents of each file, by its `/`-separated path within the
+    /// static directory.
+    files: BTreeMap<String, Contents>,
+}
+
+/// Where to get a static file's contents from.
+#[derive(Clone, Debug)]
+enum Contents {
+    Builtin(&'static [u8]),
+    File(PathBuf),
+    Generated(Vec<u8>),
+}
+
+impl StaticFiles {
+    /// Find the static files of `theme`, and of the site in `static_dir`,
+    /// leaving out any whose names match one of the `ignore` patterns.
+    pub fn collect(theme: &Theme, static_dir: &Path, ignore: &[String]) -> Result<StaticFiles> {
+        let mut files = BTreeMap::new();
+        for name in theme.static_files()? {
+            let path = url_path(&name);
+            if is_ignored(&path, ignore) {
+                continue;
+            }
+            let contents = match theme.static_file(&name) {
+                StaticFile::Builtin(contents) => Contents::Builtin(contents),
+                StaticFile::Path(path) => Contents::File(path),
+            };
+            files.insert(path, contents);
+        }
+        if static_dir.is_dir() {
+            let found = walk::files(static_dir, |path| is_ignored(&url_path(path), ignore))
+                .map_err(|e| Error::Io(static_dir.to_owned(), e))?;
+            for relative in found {
+                files.insert(
+                    url_path(&relative),
+                    Contents::File(static_dir.join(relative)),
+                );
+            }
+        }
+        Ok(StaticFiles { files })
+    }
+
+    /// The paths of all the files, within the static directory.
+    pub fn paths(&self) -> Vec<String> {
+        self.files.keys().cloned().collect()
+    }
+
+    /// Is there a file at `path`?
+    pub fn contains(&self, path: &str) -> bool {
+        self.files.contains_key(path)
+    }
+
+    /// The file that the one at `path` is a copy of, unless it's one blake
+    /// has itself or makes.
+    pub fn source(&self, path: &str) -> Option<&Path> {
+        match self.files.get(path) {
+            Some(Contents::File(source)) => Some(source),
+            _ => None,
+        }
+    }
+
+    /// The contents of the file at `path`.
+    pub fn read(&self, path: &str) -> Result<Cow<'_, [u8]>> {
+        match self.files.get(path) {
+            Some(Contents::Builtin(contents)) => Ok(Cow::Borrowed(contents)),
+            Some(Contents::Generated(contents)) => Ok(Cow::Borrowed(contents)),
+            Some(Contents::File(source)) => Ok(Cow::Owned(
+                fs::read(source).map_err(|e| Error::Io(source.clone(), e))?,
+            )),
+            None => Err(Error::Asset(
+                PathBuf::from(path),
+                String::from("No such static file"),
+            )),
+        }
+    }
+
+    /// Publish the file at `from` as `to` instead.
+    pub fn rename(&mut self, from: &str, to: &str) {
+        if let Some(contents) = self.files.remove(from) {
+            self.files.insert(to.to_owned(), contents);
+        }
+    }
+
+    /// Publish the file at `from` as `to` as well.
+    pub fn copy(&mut self, from: &str, to: &str) {
+        if let Some(contents) = self.files.get(from).cloned() {
+            self.files.insert(to.to_owned(), contents);
+        }
+    }
+
+    /// Add a file that blake makes, rather than one from a static directory.
+    pub fn add(&mut self, path: &str, contents: Vec<u8>) {
+        self.files
+            .insert(path.to_owned(), Contents::Generated(contents));
+    }
+
+    /// Make `dest_dir` hold exactly these files.
+    ///
+    /// Only files that are new or have changed are written, and files that
+    /// aren't static files any more are removed. Copies keep the modification
+    /// times of their originals, so unchanged files are left alone next time.
+    pub fn sync(&self, dest_dir: &Path) -> Result<()> {
+        fs::create_dir_all(dest_dir).map_err(|e| Error::Io(dest_dir.to_owned(), e))?;
+        self.remove_others(dest_dir, Path::new(""))?;
+        let mut changed = 0;
+        for (path, contents) in &self.files {
+            let dest = dest_dir.join(path);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent).map_err(|e| Error::Io(parent.to_owned(), e))?;
+            }
+            let written = match contents {
+                Contents::Builtin(contents) => write_if_changed(contents, &dest)?,
+                Contents::Generated(contents) => write_if_changed(contents, &dest)?,
+                Contents::File(source) => copy_if_changed(source, &dest)?,
+            };
+            if written {
+                log::debug!("Wrote static file {}", path);
+                changed += 1;
+            }
+        }
+        log::debug!("{} of {} static files changed", changed, self.files.len());
+        Ok(())
+    }
+
+    /// Remove everything under `root.join(dir)` that isn't one of the files,
+    /// including directories that are left empty.
+    fn remove_others(&self, root: &Path, dir: &Path) -> Result<()> {
+        let full_dir = root.join(dir);
+        let io_error = |e| Error::Io(full_dir.clone(), e);
+        for entry in fs::read_dir(&full_dir).map_err(io_error)? {
+            let path = entry.map_err(io_error)?.path();
+            let relative = dir.join(path.file_name().expect("Directory entries have names."));
+            let wanted = self.files.contains_key(&url_path(&relative));
+            let file_type = fs::symlink_metadata(&path)
+                .map_err(|e| Error::Io(path.clone(), e))?
+                .file_type();
+            let removed = if file_type.is_dir() && wanted {
+                fs::remove_dir_all(&path)
+            } else if file_type.is_dir() {
+                self.remove_others(root, &relative)?;
+                match fs::read_dir(&path).map(|mut entries| entries.next()) {
+                    Ok(None) => fs::remove_dir(&path),
+                    other => other.map(|_| ()),
+                }
+            } else if !wanted {
+                log::debug!("Removed static file {}", url_path(&relative));
+                fs::remove_file(&path)
+            } else {
+                Ok(())
+            };
+            removed.map_err(|e| Error::Io(path, e))?;
+        }
+        Ok(())
+    }
+}
+
+/// `path` with `/` between its parts, whatever the platform.
+fn url_path(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Whether the file or directory at `path` matches one of the `ignore`
+/// patterns, which are names that can contain `*` wildcards.
+fn is_ignored(path: &str, ignore: &[String]) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    ignore.iter().any(|pattern| matches(pattern, name))
+}
+
+fn matches(pattern: &str, name: &str) -> bool {
+    match pattern.find('*') {
+        None => pattern == name,
+        Some(star) => {
+            let (prefix, rest) = (&pattern[..star], &pattern[star + 1..]);
+            name.starts_with(prefix)
+                && (prefix.len()..=name.len())
+                    .filter(|i| name.is_char_boundary(*i))
+                    .any(|i| matches(rest, &name[i..]))
+        }
+    }
+}
+
+/// Write `contents` to `dest`, unless it already has them.
+///
+/// Returns whether it wrote anything.
+pub fn write_if_changed(contents: &[u8], dest: &Path) -> Result<bool> {
+    if fs::read(dest).is_ok_and(|existing| existing == contents) {
+        return Ok(false);
+    }
+    remove_file(dest)?;
+    fs::write(dest, contents).map_err(|e| Error::Io(dest.to_owned(), e))?;
+    Ok(true)
+}
+
+/// Copy `source` to `dest`, keeping its modification time, unless `dest`
+/// already looks like the same file.
+///
+/// Returns whether it copied anything.
+pub fn copy_if_changed(source: &Path, dest: &Path) -> Result<bool> {
+    let source_error = |e| Error::Io(source.to_owned(), e);
+    let dest_error = |e| Error::Io(dest.to_owned(), e);
+    let metadata = fs::metadata(source).map_err(source_error)?;
+    let modified = metadata.modified().map_err(source_error)?;
+    if let Ok(existing) = fs::metadata(dest) {
+        if existing.len() == metadata.len() && existing.modified().ok() == Some(modified) {
+            return Ok(false);
+        }
+    }
+    remove_file(dest)?;
+    fs::copy(source, dest).map_err(dest_error)?;
+    fs::File::options()
+        .write(true)
+        .open(dest)
+        .and_then(|file| file.set_modified(modified))
+        .map_err(dest_error)?;
+    Ok(true)
+}
+
+/// Remove `dest`, if it's there, so that writing to it makes a new file.
+///
+/// A build starts with hard links to the files in the live one, which writing
+/// over would change too.
+pub fn remove_file(dest: &Path) -> Result<()> {
+    match fs::remove_file(dest) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(Error::Io(dest.to_owned(), e)),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn ignore() -> Vec<String> {
+        crate::config::AssetsConfig::default().ignore
+    }
+
+    #[test]
+    fn test_is_ignored() {
+        let ignore = ignore();
+        assert!(is_ignored("css/.DS_Store", &ignore));
+        assert!(is_ignored("tufte.css~", &ignore));
+        assert!(is_ignored(".tufte.css.swp", &ignore));
+        assert!(is_ignored("#notes.txt#", &ignore));
+        assert!(!is_ignored("tufte.css", &ignore));
+        assert!(!is_ignored("swp/tufte.css", &ignore));
+    }
+
+    #[test]
+    fn test_sync() {
+        let root = TempDir::new("sync");
+        let root = root.path();
+        let site_static = root.join("static");
+        let output = root.join("output");
+        fs::create_dir_all(site_static.join("js")).unwrap();
+        fs::write(site_static.join("js/site.js"), "one").unwrap();
+        fs::write(site_static.join("js/site.js~"), "backup").unwrap();
+        fs::create_dir_all(output.join("old")).unwrap();
+        fs::write(output.join("old/gone.js"), "").unwrap();
+
+        let theme = Theme::find(crate::theme::DEFAULT_THEME, None).unwrap();
+        let files = StaticFiles::collect(&theme, &site_static, &ignore()).unwrap();
+        files.sync(&output).unwrap();
+        let first = fs::metadata(output.join("js/site.js")).unwrap();
+        files.sync(&output).unwrap();
+        let second = fs::metadata(output.join("js/site.js")).unwrap();
+        let source = fs::metadata(site_static.join("js/site.js")).unwrap();
+        let published = walk::files(&output, |_| false).unwrap();
+
+        assert_eq!(files.paths(), ["js/site.js", "tufte.css"]);
+        assert_eq!(
+            published,
+            [PathBuf::from("js/site.js"), PathBuf::from("tufte.css")]
+        );
+        assert_eq!(first.modified().unwrap(), source.modified().unwrap());
+        assert_eq!(second.modified().unwrap(), first.modified().unwrap());
+    }
+}
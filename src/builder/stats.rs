@@ -0,0 +1,138 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use chrono::prelude::*;
+
+use super::html;
+
+/// How many of the longest posts to report.
+const LONGEST_POSTS: usize = 10;
+
+/// Statistics about all the posts on a site.
+#[derive(Debug, Default)]
+pub struct Stats {
+    posts: Vec<PostStats>,
+    words_per_year: BTreeMap<i32, usize>,
+    words_per_month: BTreeMap<(i32, u32), usize>,
+}
+
+#[derive(Debug)]
+struct PostStats {
+    date: DateTime<FixedOffset>,
+    title: Option<String>,
+    word_count: usize,
+}
+
+impl Stats {
+    pub fn new(posts: &[html::Post]) -> Stats {
+        let mut stats = Stats::default();
+        for post in posts {
+            stats.add(
+                *post.date(),
+                post.title().map(|title| title.text().to_owned()),
+                post.word_count(),
+            );
+        }
+        stats
+    }
+
+    fn add(&mut self, date: DateTime<FixedOffset>, title: Option<String>, word_count: usize) {
+        *self.words_per_year.entry(date.year()).or_insert(0) += word_count;
+        *self
+            .words_per_month
+            .entry((date.year(), date.month()))
+            .or_insert(0) += word_count;
+        self.posts.push(PostStats {
+            date,
+            title,
+            word_count,
+        });
+    }
+
+    pub fn total_words(&self) -> usize {
+        self.posts.iter().map(|post| post.word_count).sum()
+    }
+
+    fn longest_posts(&self) -> Vec<&PostStats> {
+        let mut posts = self.posts.iter().collect::<Vec<_>>();
+        posts.sort_by(|a, b| b.word_count.cmp(&a.word_count).then(b.date.cmp(&a.date)));
+        posts.truncate(LONGEST_POSTS);
+        posts
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Posts: {}", self.posts.len())?;
+        writeln!(f, "Words: {}", self.total_words())?;
+        writeln!(f)?;
+        writeln!(f, "Words per year:")?;
+        for (year, words) in &self.words_per_year {
+            writeln!(f, "  {}     {:>8}", year, words)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "Words per month:")?;
+        for ((year, month), words) in &self.words_per_month {
+            writeln!(f, "  {}-{:02}  {:>8}", year, month, words)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "Longest posts:")?;
+        for post in self.longest_posts() {
+            writeln!(
+                f,
+                "  {:>8}  {}  {}",
+                post.word_count,
+                post.date.format("%Y-%m-%d"),
+                post.title.as_deref().unwrap_or("(untitled)")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc() -> FixedOffset {
+        FixedOffset::east(0)
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut stats = Stats::default();
+        stats.add(utc().ymd(2019, 11, 25).and_hms(10, 30, 0), None, 100);
+        stats.add(
+            utc().ymd(2019, 11, 26).and_hms(9, 0, 0),
+            Some(String::from("long")),
+            300,
+        );
+        stats.add(utc().ymd(2020, 1, 1).and_hms(0, 0, 0), None, 50);
+        assert_eq!(stats.total_words(), 450);
+        assert_eq!(
+            stats.words_per_year.into_iter().collect::<Vec<_>>(),
+            vec![(2019, 400), (2020, 50)]
+        );
+        assert_eq!(
+            stats.words_per_month.into_iter().collect::<Vec<_>>(),
+            vec![((2019, 11), 400), ((2020, 1), 50)]
+        );
+    }
+
+    #[test]
+    fn test_longest_posts() {
+        let mut stats = Stats::default();
+        stats.add(utc().ymd(2019, 11, 25).and_hms(10, 30, 0), None, 100);
+        stats.add(
+            utc().ymd(2019, 11, 26).and_hms(9, 0, 0),
+            Some(String::from("long")),
+            300,
+        );
+        let longest = stats
+            .longest_posts()
+            .iter()
+            .map(|post| post.word_count)
+            .collect::<Vec<_>>();
+        assert_eq!(longest, vec![300, 100]);
+    }
+}
@@ -0,0 +1,198 @@
+use super::relative;
+use crate::config::Config;
+
+/// Turns the URLs of pages within the site into URLs that work where the
+/// site is deployed.
+///
+/// Everywhere else in blake, URLs are relative to the root of the site, like
+/// `/posts/foo.html`. If the site is served from a subdirectory, say
+/// `https://example.org/notebook/`, then links need the `/notebook` prefix,
+/// and feeds, canonical links and the like need the full URL.
+///
+/// A site built with relative URLs can be browsed straight from disk, so its
+/// pages have their links rewritten to be relative to the page itself.
+#[derive(Clone, Debug, Default)]
+pub struct Urls {
+    /// Scheme and host, like `https://example.org`. Empty if unknown.
+    origin: String,
+    /// Path the site is served from, like `/notebook`. Empty for the root.
+    prefix: String,
+    /// Whether to make links within pages relative.
+    relative: bool,
+}
+
+impl Urls {
+    /// Make URLs for a site served from `base_url`.
+    ///
+    /// The path prefix comes from `base_url`, unless `path_prefix` is given.
+    pub fn new(base_url: &str, path_prefix: Option<&str>) -> Urls {
+        let base_url = base_url.trim_end_matches('/');
+        let (origin, base_path) = match base_url.find("://") {
+            Some(scheme_end) => match base_url[scheme_end + 3..].find('/') {
+                Some(host_end) => base_url.split_at(scheme_end + 3 + host_end),
+                None => (base_url, ""),
+            },
+            None => ("", base_url),
+        };
+        let prefix = path_prefix.unwrap_or(base_path).trim_matches('/');
+        let prefix = if prefix.is_empty() {
+            String::new()
+        } else {
+            format!("/{}", prefix)
+        };
+        Urls {
+            origin: origin.to_owned(),
+            prefix,
+            relative: false,
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Urls {
+        let mut urls = Urls::new(&config.base_url, config.path_prefix.as_deref());
+        urls.relative = config.relative_urls;
+        urls
+    }
+
+    /// Finish off the HTML of the page at `url`, ready to be written.
+    pub fn page(&self, url: &str, html: String) -> String {
+        if self.relative {
+            relative::relativize(&html, url, &self.prefix)
+        } else {
+            html
+        }
+    }
+
+    /// The path to a page on the site, including any prefix.
+    ///
+    /// Links to other sites and to fragments are returned unchanged.
+    pub fn path(&self, url: &str) -> String {
+        if is_external(url) {
+            url.to_owned()
+        } else if url.starts_with('/') {
+            format!("{}{}", self.prefix, url)
+        } else {
+            format!("{}/{}", self.prefix, url)
+        }
+    }
+
+    /// The full URL of a page on the site.
+    ///
+    /// If the site doesn't have a base URL, this is the same as `path`.
+    pub fn absolute(&self, url: &str) -> String {
+        if is_external(url) {
+            url.to_owned()
+        } else {
+            format!("{}{}", self.origin, self.path(url))
+        }
+    }
+}
+
+/// Is this URL relative to the page it's on, like `diagram.png`?
+pub fn is_relative(url: &str) -> bool {
+    !url.is_empty() && !url.starts_with('/') && !is_external(url)
+}
+
+/// The URL on the site that the relative `url` on the page at `base` points
+/// to, e.g. `/now/chart.png` for `chart.png` on `/now/`.
+pub fn resolve(base: &str, url: &str) -> String {
+    let dir = &base[..base.rfind('/').map_or(0, |i| i + 1)];
+    let joined = format!("{}{}", dir, url);
+    let mut parts = Vec::new();
+    for part in joined.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    let mut resolved = format!("/{}", parts.join("/"));
+    if !parts.is_empty() && (joined.ends_with('/') || joined.ends_with("/.")) {
+        resolved.push('/');
+    }
+    resolved
+}
+
+/// Does this URL point somewhere other than a page on the site?
+fn is_external(url: &str) -> bool {
+    url.starts_with("//") || url.starts_with('#') || has_scheme(url)
+}
+
+fn has_scheme(url: &str) -> bool {
+    match url.find(':') {
+        None => false,
+        Some(end) => {
+            let scheme = &url[..end];
+            scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_base_url() {
+        let urls = Urls::new("", None);
+        assert_eq!(urls.path("/posts/a.html"), "/posts/a.html");
+        assert_eq!(urls.absolute("/posts/a.html"), "/posts/a.html");
+    }
+
+    #[test]
+    fn test_base_url_at_root() {
+        let urls = Urls::new("https://example.org/", None);
+        assert_eq!(urls.path("/posts/a.html"), "/posts/a.html");
+        assert_eq!(urls.absolute("/"), "https://example.org/");
+    }
+
+    #[test]
+    fn test_base_url_with_path() {
+        let urls = Urls::new("https://example.org/notebook/", None);
+        assert_eq!(urls.path("/static/tufte.css"), "/notebook/static/tufte.css");
+        assert_eq!(urls.path("/"), "/notebook/");
+        assert_eq!(
+            urls.absolute("/posts/2019-11-25-10:30.html"),
+            "https://example.org/notebook/posts/2019-11-25-10:30.html"
+        );
+    }
+
+    #[test]
+    fn test_explicit_prefix() {
+        let urls = Urls::new("https://example.org", Some("notebook/"));
+        assert_eq!(urls.path("/feed.xml"), "/notebook/feed.xml");
+        assert_eq!(
+            urls.absolute("feed.xml"),
+            "https://example.org/notebook/feed.xml"
+        );
+    }
+
+    #[test]
+    fn test_external_urls() {
+        let urls = Urls::new("https://example.org/notebook", None);
+        for url in &[
+            "https://other.org/",
+            "//cdn.org/x.css",
+            "mailto:jml@mumak.net",
+            "#sn-1",
+        ] {
+            assert_eq!(urls.path(url), *url);
+            assert_eq!(urls.absolute(url), *url);
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(resolve("/now/", "chart.png"), "/now/chart.png");
+        assert_eq!(resolve("/about.html", "./static/me.png"), "/static/me.png");
+        assert_eq!(
+            resolve("/2019/post.html", "../static/plot.png"),
+            "/static/plot.png"
+        );
+        assert_eq!(resolve("/a/b/", "../../../c/"), "/c/");
+    }
+}
@@ -0,0 +1,490 @@
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::{fmt, fs, io};
+
+use crate::theme::{self, Theme};
+
+/// The name of the configuration file blake looks for by default.
+pub const CONFIG_FILE: &str = "blake.toml";
+
+const POSTS_DIR: &str = "/Users/jml/src/notebook/posts/";
+const PAGES_DIR: &str = "/Users/jml/src/notebook/pages/";
+const STATIC_DIR: &str = "/Users/jml/src/notebook/static/";
+const OUTPUT_DIR: &str = "/Users/jml/src/blake/output/";
+
+/// Configuration for a blake site.
+///
+/// Every field has a default, so a config file only needs to mention the
+/// things it wants to change.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub posts_dir: PathBuf,
+    /// Standalone pages, like About, that aren't posts. Optional.
+    pub pages_dir: PathBuf,
+    pub static_dir: PathBuf,
+    pub output_dir: PathBuf,
+    /// Templates that replace the theme's templates of the same name.
+    pub templates_dir: Option<PathBuf>,
+    /// The name of the theme that gives the site its look.
+    pub theme: String,
+    /// Where to look for themes other than the built-in ones.
+    pub themes_dir: Option<PathBuf>,
+    /// Stylesheets to link from every page after the theme's, as paths within
+    /// the site, e.g. `/static/site.css`. None by default.
+    pub stylesheets: Vec<String>,
+    /// Where the site is published, e.g. `https://example.org/notebook/`.
+    /// Used for links that must be absolute, such as those in the feed.
+    pub base_url: String,
+    /// Path the site is served from, if it differs from the path in `base_url`.
+    pub path_prefix: Option<String>,
+    /// Make links between pages relative, so the site can be browsed from disk.
+    pub relative_urls: bool,
+    /// Stop building at the first post that fails, instead of reporting
+    /// every one that does.
+    pub fail_fast: bool,
+    /// The IANA name of the timezone posts are written in, e.g. `America/New_York`.
+    pub timezone: Tz,
+    /// The ways post filenames can be written, in order of preference.
+    pub filename_patterns: Vec<FilenamePattern>,
+    /// Template for post URLs, e.g. `/{year}/{month}/{slug}/`. URLs ending in
+    /// a slash are written to `index.html` in that directory.
+    pub permalink: String,
+    pub site: SiteConfig,
+    pub title: TitleConfig,
+    pub summary: SummaryConfig,
+    pub stats: StatsConfig,
+    pub images: ImagesConfig,
+    pub assets: AssetsConfig,
+    pub redirects: RedirectsConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            posts_dir: PathBuf::from(POSTS_DIR),
+            pages_dir: PathBuf::from(PAGES_DIR),
+            static_dir: PathBuf::from(STATIC_DIR),
+            output_dir: PathBuf::from(OUTPUT_DIR),
+            templates_dir: None,
+            theme: String::from(theme::DEFAULT_THEME),
+            themes_dir: None,
+            stylesheets: Vec::new(),
+            base_url: String::new(),
+            path_prefix: None,
+            relative_urls: false,
+            fail_fast: false,
+            timezone: Tz::UTC,
+            filename_patterns: vec![
+                FilenamePattern::DateTime,
+                FilenamePattern::Date,
+                FilenamePattern::DateSlug,
+            ],
+            permalink: String::from("/posts/{name}.html"),
+            site: SiteConfig::default(),
+            title: TitleConfig::default(),
+            summary: SummaryConfig::default(),
+            stats: StatsConfig::default(),
+            images: ImagesConfig::default(),
+            assets: AssetsConfig::default(),
+            redirects: RedirectsConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Load configuration from a TOML file.
+    ///
+    /// Anything the file doesn't set comes from the theme's defaults, and then
+    /// from blake's.
+    pub fn load(path: &Path) -> Result<Config, Error> {
+        let contents = fs::read_to_string(path).map_err(|e| Error::Io(path.to_owned(), e))?;
+        let site = toml::from_str(&contents).map_err(|e| Error::Parse(path.to_owned(), e))?;
+        Config::from_site(path, site)
+    }
+
+    /// Load configuration from a TOML file, using the defaults if it doesn't exist.
+    pub fn load_or_default(path: &Path) -> Result<Config, Error> {
+        if path.exists() {
+            Config::load(path)
+        } else {
+            Config::from_site(path, toml::Value::Table(toml::value::Table::new()))
+        }
+    }
+
+    /// Make configuration from the site's settings, layered over its theme's.
+    fn from_site(path: &Path, site: toml::Value) -> Result<Config, Error> {
+        let theme_name = site
+            .get("theme")
+            .and_then(|theme| theme.as_str())
+            .unwrap_or(theme::DEFAULT_THEME);
+        let themes_dir = site
+            .get("themes_dir")
+            .and_then(|themes_dir| themes_dir.as_str())
+            .map(PathBuf::from);
+        let theme = Theme::find(theme_name, themes_dir.as_deref()).map_err(Error::Theme)?;
+        let mut defaults = theme.config().clone();
+        defaults.remove("theme");
+        defaults.remove("themes_dir");
+        with_defaults(site, defaults)
+            .try_into()
+            .map_err(|e| Error::Parse(path.to_owned(), e))
+    }
+}
+
+/// A way of writing the filename of a post.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum FilenamePattern {
+    /// A date and time, like `2019-11-25-1030.md`, or `2019-11-25-10:30.md`
+    /// as `blake new` used to name them.
+    DateTime,
+    /// Just a date, like `2019-11-25.md`.
+    Date,
+    /// A date followed by a slug, like `2019-11-25-my-title.md`.
+    DateSlug,
+    /// Any name at all, with the date in the front matter.
+    FrontMatter,
+}
+
+/// Things about the site as a whole, for templates to show.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SiteConfig {
+    pub title: String,
+    pub subtitle: Option<String>,
+    pub author: Option<String>,
+    /// Links to show on every page.
+    pub nav: Vec<NavLink>,
+    /// HTML to show at the bottom of every page.
+    pub footer: Option<String>,
+    /// Anything else the templates need.
+    pub extra: toml::value::Table,
+}
+
+impl Default for SiteConfig {
+    fn default() -> Self {
+        SiteConfig {
+            title: String::from("Notebook"),
+            subtitle: None,
+            author: None,
+            nav: Vec::new(),
+            footer: None,
+            extra: toml::value::Table::new(),
+        }
+    }
+}
+
+/// A link in the site's navigation.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct NavLink {
+    pub title: String,
+    /// Where the link goes, as a path within the site or a full URL.
+    pub url: String,
+}
+
+/// How to find the title of a post.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TitleConfig {
+    /// Places to look for a title, in order of preference.
+    pub sources: Vec<TitleSource>,
+    /// Whether to remove the heading from the body when it's used as the title.
+    pub remove_heading: bool,
+}
+
+impl Default for TitleConfig {
+    fn default() -> Self {
+        TitleConfig {
+            sources: vec![TitleSource::Heading, TitleSource::FrontMatter],
+            remove_heading: true,
+        }
+    }
+}
+
+/// Somewhere a post's title can come from.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TitleSource {
+    /// The first level 1 heading in the post.
+    Heading,
+    /// The `title` field in the post's front matter.
+    FrontMatter,
+    /// The first line of the first paragraph.
+    FirstLine,
+    /// The post's date. This always succeeds, so is only ever tried last.
+    Date,
+}
+
+/// How to summarise posts on the index and in the feed.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SummaryConfig {
+    /// Show summaries instead of full posts on the index.
+    pub on_index: bool,
+    /// Show summaries instead of full posts in the feed.
+    pub in_feed: bool,
+    /// Use the first paragraph as the summary of posts that don't have one.
+    pub auto_excerpt: bool,
+    /// Text of the link from a summary to the full post. Empty for no link.
+    pub continue_reading: String,
+}
+
+impl Default for SummaryConfig {
+    fn default() -> Self {
+        SummaryConfig {
+            on_index: false,
+            in_feed: false,
+            auto_excerpt: true,
+            continue_reading: String::from("Continue reading"),
+        }
+    }
+}
+
+/// How to show the images in posts and pages.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImagesConfig {
+    /// Give local images their sizes and make smaller copies of them.
+    pub process: bool,
+    /// The widths, in pixels, of the smaller copies.
+    pub widths: Vec<u32>,
+    /// Ask browsers to load images only when they're about to be seen.
+    pub lazy: bool,
+}
+
+impl Default for ImagesConfig {
+    fn default() -> Self {
+        ImagesConfig {
+            process: true,
+            widths: vec![480, 960, 1920],
+            lazy: true,
+        }
+    }
+}
+
+/// How to publish the files in the static directory.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AssetsConfig {
+    /// Put a hash of each file's contents in its name, so caches never serve
+    /// an old version.
+    pub fingerprint: bool,
+    /// Keep a copy of each file under its own name too, for anything outside
+    /// the site that links to it.
+    pub keep_unhashed: bool,
+    /// Names of files not to publish, which can contain `*` wildcards.
+    pub ignore: Vec<String>,
+}
+
+impl Default for AssetsConfig {
+    fn default() -> Self {
+        AssetsConfig {
+            fingerprint: false,
+            keep_unhashed: false,
+            ignore: [
+                ".DS_Store",
+                "Thumbs.db",
+                "*~",
+                "*.swp",
+                "*.swo",
+                ".#*",
+                "#*#",
+            ]
+            .iter()
+            .map(|pattern| String::from(*pattern))
+            .collect(),
+        }
+    }
+}
+
+/// How to count the words in a post.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StatsConfig {
+    /// Reading speed used to estimate how long a post takes to read.
+    pub words_per_minute: usize,
+    /// Whether to count the words in code blocks.
+    pub count_code: bool,
+    /// Whether to count the words in sidenotes.
+    pub count_sidenotes: bool,
+}
+
+impl Default for StatsConfig {
+    fn default() -> Self {
+        StatsConfig {
+            words_per_minute: 200,
+            count_code: false,
+            count_sidenotes: true,
+        }
+    }
+}
+
+/// Which redirect maps to write alongside the redirect pages.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedirectsConfig {
+    /// Write a Netlify-style `_redirects` file.
+    pub netlify: bool,
+    /// Write an nginx `map` block to `redirects.nginx.conf`.
+    pub nginx: bool,
+}
+
+/// Fill in the settings missing from `value` with `defaults`, table by table.
+fn with_defaults(value: toml::Value, defaults: toml::value::Table) -> toml::Value {
+    match value {
+        toml::Value::Table(mut table) => {
+            for (key, default) in defaults {
+                let merged = match table.remove(&key) {
+                    None => default,
+                    Some(value) => match default {
+                        toml::Value::Table(default) => with_defaults(value, default),
+                        _ => value,
+                    },
+                };
+                table.insert(key, merged);
+            }
+            toml::Value::Table(table)
+        }
+        value => value,
+    }
+}
+
+/// Errors that can be caused by loading configuration.
+#[derive(Debug)]
+pub enum Error {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Theme(theme::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(path, err) => write!(f, "Cannot read config {}: {}", path.display(), err),
+            Error::Parse(path, err) => write!(f, "Invalid config {}: {}", path.display(), err),
+            Error::Theme(err) => write!(f, "Cannot load theme: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(_, err) => Some(err),
+            Error::Parse(_, err) => Some(err),
+            Error::Theme(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_config() {
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.posts_dir, PathBuf::from(POSTS_DIR));
+        assert!(config.title.remove_heading);
+        assert!(!config.summary.on_index);
+        assert_eq!(config.timezone, Tz::UTC);
+    }
+
+    #[test]
+    fn test_filename_patterns() {
+        let config: Config =
+            toml::from_str("filename_patterns = [\"date-slug\", \"front-matter\"]").unwrap();
+        assert_eq!(
+            config.filename_patterns,
+            vec![FilenamePattern::DateSlug, FilenamePattern::FrontMatter]
+        );
+    }
+
+    #[test]
+    fn test_stylesheets() {
+        let default: Config = toml::from_str("").unwrap();
+        let site: Config = toml::from_str("stylesheets = [\"/static/site.css\"]").unwrap();
+        assert!(default.stylesheets.is_empty());
+        assert_eq!(site.stylesheets, ["/static/site.css"]);
+    }
+
+    #[test]
+    fn test_timezone() {
+        let config: Config = toml::from_str("timezone = \"America/New_York\"").unwrap();
+        assert_eq!(config.timezone, Tz::America__New_York);
+    }
+
+    #[test]
+    fn test_site() {
+        let config: Config = toml::from_str(
+            "[site]
+title = \"Notes\"
+nav = [{ title = \"About\", url = \"/about/\" }]
+[site.extra]
+mastodon = \"@jml\"
+",
+        )
+        .unwrap();
+        assert_eq!(config.site.title, "Notes");
+        assert_eq!(config.site.author, None);
+        assert_eq!(
+            config.site.nav,
+            vec![NavLink {
+                title: String::from("About"),
+                url: String::from("/about/"),
+            }]
+        );
+        assert_eq!(
+            config.site.extra.get("mastodon"),
+            Some(&toml::Value::String(String::from("@jml")))
+        );
+    }
+
+    #[test]
+    fn test_theme_defaults() {
+        let site = toml::from_str(
+            "permalink = \"/{slug}/\"
+[summary]
+on_index = true
+",
+        )
+        .unwrap();
+        let defaults = toml::from_str(
+            "permalink = \"/posts/{slug}.html\"
+timezone = \"Europe/London\"
+[summary]
+on_index = false
+in_feed = true
+",
+        )
+        .unwrap();
+        let config: Config = with_defaults(site, defaults).try_into().unwrap();
+        assert_eq!(config.permalink, "/{slug}/");
+        assert_eq!(config.timezone, Tz::Europe__London);
+        assert!(config.summary.on_index);
+        assert!(config.summary.in_feed);
+    }
+
+    #[test]
+    fn test_title_sources() {
+        let config: Config = toml::from_str(
+            "[title]
+sources = [\"front-matter\", \"first-line\", \"date\"]
+remove_heading = false
+",
+        )
+        .unwrap();
+        assert_eq!(
+            config.title.sources,
+            vec![
+                TitleSource::FrontMatter,
+                TitleSource::FirstLine,
+                TitleSource::Date
+            ]
+        );
+        assert!(!config.title.remove_heading);
+    }
+}
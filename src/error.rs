@@ -0,0 +1,165 @@
+//! What can go wrong when blake builds a site, or does anything else.
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::{config, posts, theme};
+
+/// Errors that can be caused by blake's commands.
+///
+/// Each carries the path of the file it's about, where there is one.
+#[derive(Debug)]
+pub enum Error {
+    /// The site's configuration can't be loaded.
+    Config(config::Error),
+    /// The site's theme can't be found or loaded.
+    Theme(theme::Error),
+    /// A post's name, date or front matter isn't valid.
+    Source {
+        path: PathBuf,
+        /// The line the problem is on, counting from 1, if known.
+        line: Option<usize>,
+        message: String,
+    },
+    /// A post or page has Markdown that can't be made into HTML, such as a
+    /// figure with attributes blake doesn't know.
+    Markdown(PathBuf, String),
+    /// The templates in a directory can't be loaded, or a template can't be
+    /// rendered to a file.
+    Template(PathBuf, tera::Error),
+    /// A file or directory can't be read or written.
+    Io(PathBuf, io::Error),
+    /// An image or static file can't be found or processed.
+    Asset(PathBuf, String),
+    /// More than one file failed to build.
+    Files(Vec<Error>),
+}
+
+/// The result of one of blake's commands.
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// The file the error is about, if there's just one.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Error::Config(_) | Error::Theme(_) | Error::Files(_) => None,
+            Error::Source { path, .. } => Some(path),
+            Error::Markdown(path, _)
+            | Error::Template(path, _)
+            | Error::Io(path, _)
+            | Error::Asset(path, _) => Some(path),
+        }
+    }
+
+    /// A problem with the post at `path`.
+    pub(crate) fn post(path: &Path, error: posts::Error) -> Error {
+        match error {
+            posts::Error::IoError(err) => Error::Io(path.to_owned(), err),
+            error => Error::Source {
+                path: path.to_owned(),
+                line: error.line(),
+                message: error.to_string(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Config(err) => write!(f, "{}", err),
+            Error::Theme(err) => write!(f, "{}", err),
+            Error::Source {
+                path,
+                line: Some(line),
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+            Error::Source {
+                path,
+                line: None,
+                message,
+            } => write!(f, "{}: {}", path.display(), message),
+            Error::Markdown(path, message) => write!(f, "{}: {}", path.display(), message),
+            Error::Template(path, err) => {
+                // Tera puts the details, such as which variable is missing,
+                // in the errors that caused this one.
+                write!(f, "{}: {}", path.display(), err)?;
+                for cause in err.iter().skip(1) {
+                    write!(f, ": {}", cause)?;
+                }
+                Ok(())
+            }
+            Error::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            Error::Asset(path, message) => write!(f, "{}: {}", path.display(), message),
+            Error::Files(errors) => {
+                write!(f, "{} files could not be built:", errors.len())?;
+                for error in errors {
+                    write!(f, "\n  {}", error)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Config(err) => Some(err),
+            Error::Theme(err) => Some(err),
+            Error::Template(_, err) => Some(err),
+            Error::Io(_, err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<config::Error> for Error {
+    fn from(error: config::Error) -> Self {
+        Error::Config(error)
+    }
+}
+
+impl From<theme::Error> for Error {
+    fn from(error: theme::Error) -> Self {
+        Error::Theme(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let source = Error::Source {
+            path: PathBuf::from("posts/a.md"),
+            line: Some(3),
+            message: String::from("Cannot parse front matter"),
+        };
+        let missing = Error::Io(
+            PathBuf::from("posts/b.md"),
+            io::Error::from(io::ErrorKind::NotFound),
+        );
+        let missing_message = missing.to_string();
+        let files = Error::Files(vec![source, missing]);
+
+        assert_eq!(
+            files.to_string(),
+            format!(
+                "2 files could not be built:\n  posts/a.md:3: Cannot parse front matter\n  {}",
+                missing_message
+            )
+        );
+        assert!(missing_message.starts_with("posts/b.md: "));
+    }
+
+    #[test]
+    fn test_post_line() {
+        let error = crate::front_matter::split("+++\ntitle = \n+++\n").unwrap_err();
+        let error = Error::post(Path::new("posts/a.md"), error.into());
+        assert!(matches!(error, Error::Source { line: Some(2), .. }));
+        assert!(error.to_string().starts_with("posts/a.md:2: "));
+    }
+}
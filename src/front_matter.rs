@@ -0,0 +1,127 @@
+use serde::Deserialize;
+use std::fmt;
+use toml::value::Datetime;
+
+/// The line that starts and ends a block of front matter.
+const DELIMITER: &str = "+++";
+
+/// Metadata about a post, written as TOML at the top of the post.
+///
+/// ```text
+/// +++
+/// title = "A post"
+/// +++
+///
+/// The body of the post.
+/// ```
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct FrontMatter {
+    pub title: Option<String>,
+    /// When the post was written, for posts whose filenames don't say.
+    pub date: Option<Datetime>,
+    /// A summary of the post, in Markdown.
+    pub summary: Option<String>,
+    /// Other URLs that should redirect to the post.
+    pub aliases: Vec<String>,
+    /// What the post is about, for grouping it with similar posts.
+    pub tags: Vec<String>,
+}
+
+/// Split the front matter off the top of a post.
+///
+/// Returns the parsed front matter and the remaining body. If the post has no
+/// front matter, returns the default front matter and the whole post.
+pub fn split(contents: &str) -> Result<(FrontMatter, &str), Error> {
+    match find_front_matter(contents) {
+        None => Ok((FrontMatter::default(), contents)),
+        Some((front_matter, body)) => Ok((toml::from_str(front_matter).map_err(Error)?, body)),
+    }
+}
+
+fn find_front_matter(contents: &str) -> Option<(&str, &str)> {
+    let rest = strip_delimiter_line(contents)?;
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if let Some(body) = strip_delimiter_line(line) {
+            let body_start = offset + line.len() - body.len();
+            return Some((&rest[..offset], &rest[body_start..]));
+        }
+        offset += line.len();
+    }
+    None
+}
+
+/// If `text` starts with a delimiter line, return everything after it.
+fn strip_delimiter_line(text: &str) -> Option<&str> {
+    let rest = text.strip_prefix(DELIMITER)?;
+    let rest = rest.strip_prefix('\r').unwrap_or(rest);
+    if rest.is_empty() {
+        Some(rest)
+    } else {
+        rest.strip_prefix('\n')
+    }
+}
+
+/// Front matter that isn't valid TOML, or that has fields we don't know.
+#[derive(Debug)]
+pub struct Error(toml::de::Error);
+
+impl Error {
+    /// The line of the post the error is on, counting from 1, if known.
+    pub fn line(&self) -> Option<usize> {
+        // TOML counts from 0 within the front matter, which starts after the
+        // delimiter line.
+        self.0.line_col().map(|(line, _)| line + 2)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Leave out TOML's position, which is within the front matter rather
+        // than the post.
+        let message = self.0.to_string();
+        match (self.0.line_col(), message.rfind(" at line ")) {
+            (Some(_), Some(position)) => write!(f, "{}", &message[..position]),
+            _ => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_front_matter() {
+        let contents = "# title\n\nbody\n";
+        let (front_matter, body) = split(contents).unwrap();
+        assert_eq!(front_matter, FrontMatter::default());
+        assert_eq!(body, contents);
+    }
+
+    #[test]
+    fn test_front_matter() {
+        let contents = "+++\ntitle = \"A post\"\n+++\n\nbody\n";
+        let (front_matter, body) = split(contents).unwrap();
+        assert_eq!(front_matter.title, Some(String::from("A post")));
+        assert_eq!(body, "\nbody\n");
+    }
+
+    #[test]
+    fn test_unterminated_front_matter() {
+        let contents = "+++\ntitle = \"A post\"\n";
+        let (front_matter, body) = split(contents).unwrap();
+        assert_eq!(front_matter, FrontMatter::default());
+        assert_eq!(body, contents);
+    }
+
+    #[test]
+    fn test_invalid_front_matter() {
+        let error = split("+++\ntitle = \"A post\"\nsummary = \n+++\n").unwrap_err();
+        assert_eq!(error.line(), Some(3));
+        assert!(!error.to_string().contains("at line"));
+    }
+}
@@ -1,87 +1,253 @@
 use chrono::prelude::*;
-use std::error::Error;
+use std::fmt;
 use std::fs;
 use std::io;
 use std::path::Path;
 use std::process;
+use std::time::Instant;
 
 mod builder;
+pub mod config;
+pub mod error;
+mod front_matter;
+mod pages;
 mod posts;
+#[cfg(test)]
+mod testing;
+pub mod theme;
+mod walk;
 
+pub use builder::{Renderer, Summary};
+use config::{Config, FilenamePattern};
+pub use error::{Error, Result};
 use posts::Posts;
+use theme::Theme;
 
-const POST_DATE_FORMAT: &str = "%Y-%m-%d-%H:%M";
-const POSTS_DIR: &str = "/Users/jml/src/notebook/posts/"; // TODO: Parameterize POSTS_DIR.
-const STATIC_DIR: &str = "/Users/jml/src/notebook/static/"; // TODO: Parameterize STATIC_DIR.
-const OUTPUT_DIR: &str = "/Users/jml/src/blake/output/"; // TODO: Parameterize OUTPUT_DIR.
+const POST_DATE_FORMAT: &str = "%Y-%m-%d-%H%M";
+/// How posts named after the time used to be named, with a colon that some
+/// filesystems don't allow.
+const OLD_POST_DATE_FORMAT: &str = "%Y-%m-%d-%H:%M";
 
 /// Create a new blog post.
-pub fn new_post() -> io::Result<()> {
-    let now = Utc::now();
-    let name = format!("{}", now.format(POST_DATE_FORMAT));
-    let posts = Posts::new(Path::new(POSTS_DIR).to_owned());
-    edit_and_commit_post(&posts, &name)
+///
+/// Posts are named after the current time, unless they have a slug, in which
+/// case they are named after the date and the slug. If there's a title but no
+/// slug, we make a slug from the title. Either way, the name is one that the
+/// site's `filename_patterns` allow.
+pub fn new_post(config: &Config, title: Option<&str>, slug: Option<&str>) -> Result<()> {
+    let now = Utc::now().with_timezone(&config.timezone);
+    let slug = slug.or(title).map(posts::slugify);
+    let slug = slug.as_deref().filter(|slug| !slug.is_empty());
+    let (name, mut skeleton) =
+        new_post_name(&config.filename_patterns, &now, slug).ok_or_else(|| Error::Source {
+            path: config.posts_dir.clone(),
+            line: None,
+            message: String::from(
+                "None of the filename_patterns can name a post without a slug: \
+                 give it a title or a slug",
+            ),
+        })?;
+    if let Some(title) = title {
+        skeleton.push_str(&format!("# {}\n\n", title));
+    }
+    let skeleton = Some(skeleton).filter(|skeleton| !skeleton.is_empty());
+    edit_and_commit_post(&open_posts(config), &name, skeleton)
+}
+
+/// The name for a post written at `now`, from the first of `patterns` that
+/// uses the slug, if there is one, or else the first that doesn't need one.
+///
+/// Returns the name and anything the post must start with, such as front
+/// matter with the date, or `None` if no pattern can name the post.
+fn new_post_name<Tz: TimeZone>(
+    patterns: &[FilenamePattern],
+    now: &DateTime<Tz>,
+    slug: Option<&str>,
+) -> Option<(String, String)>
+where
+    Tz::Offset: fmt::Display,
+{
+    let with_slug = slug.and_then(|slug| {
+        patterns.iter().find_map(|pattern| match pattern {
+            FilenamePattern::DateSlug => Some((
+                format!("{}-{}", now.format("%Y-%m-%d"), slug),
+                String::new(),
+            )),
+            FilenamePattern::FrontMatter => Some((
+                slug.to_owned(),
+                format!(
+                    "+++\ndate = {}\n+++\n\n",
+                    now.format("%Y-%m-%dT%H:%M:%S%:z")
+                ),
+            )),
+            _ => None,
+        })
+    });
+    with_slug.or_else(|| {
+        patterns
+            .iter()
+            .find_map(|pattern| match pattern {
+                FilenamePattern::DateTime => Some(now.format(POST_DATE_FORMAT).to_string()),
+                FilenamePattern::Date => Some(now.format("%Y-%m-%d").to_string()),
+                _ => None,
+            })
+            .map(|name| (name, String::new()))
+    })
 }
 
-pub fn edit_post() -> io::Result<()> {
-    let posts = Posts::new(Path::new(POSTS_DIR).to_owned());
-    let latest_file = posts.get_latest_file()?;
+pub fn edit_post(config: &Config) -> Result<()> {
+    let posts = open_posts(config);
+    let latest_file = posts
+        .get_latest_file()
+        .map_err(|e| Error::Io(config.posts_dir.clone(), e))?;
     // TODO: Return errors for not finding posts.
     // TODO: See if we can avoid nested match.
     match latest_file {
         None => {
-            println!("Could not find post to edit.");
+            log::warn!("Could not find post to edit.");
             Ok(())
         }
         Some(path) => match path.file_stem().and_then(|stem| stem.to_str()) {
             None => {
-                println!("Could not find post to edit.");
+                log::warn!("Could not find post to edit.");
                 Ok(())
             }
-            Some(name) => edit_and_commit_post(&posts, name),
+            Some(name) => edit_and_commit_post(&posts, name, None),
         },
     }
 }
 
-pub fn build() -> Result<(), Box<dyn Error>> {
-    let path = Path::new(OUTPUT_DIR);
-    let posts = Posts::new(Path::new(POSTS_DIR).to_owned());
-    let output = builder::OutputPath {
-        path: path.to_owned(),
-    };
-    builder::build(Path::new(STATIC_DIR), &posts, &output)
+/// Build the site, logging a summary of what changed.
+pub fn build(config: &Config) -> Result<Summary> {
+    let theme = site_theme(config)?;
+    let mut renderer = Renderer::new(config, &theme)?;
+    build_with(config, &theme, &mut renderer)
+}
+
+/// Build the site again with `config`, which may have changed since the
+/// earlier build, loading its theme and templates again.
+///
+/// For long-running commands that rebuild the site whenever it changes.
+pub fn rebuild(config: &Config, renderer: &mut Renderer) -> Result<Summary> {
+    let theme = site_theme(config)?;
+    renderer.reload(config, &theme)?;
+    build_with(config, &theme, renderer)
+}
+
+fn build_with(config: &Config, theme: &Theme, renderer: &mut Renderer) -> Result<Summary> {
+    let start = Instant::now();
+    let posts = open_posts(config);
+    let pages = pages::Pages::new(config.pages_dir.clone());
+    let staging = builder::Staging::new(&config.output_dir);
+    let output = staging.start()?;
+    let mut summary = builder::build(config, theme, renderer, &posts, &pages, &output)?;
+    staging.finish(&output)?;
+    summary.elapsed = start.elapsed();
+    log::info!("Built {}: {}", config.output_dir.display(), summary);
+    Ok(summary)
+}
+
+/// Put the site's previous build back in place of the current one.
+pub fn rollback(config: &Config) -> Result<()> {
+    builder::Staging::new(&config.output_dir).rollback()
+}
+
+/// List the themes the site can use, marking the one it does use.
+pub fn list_themes(config: &Config) -> Result<()> {
+    for theme in Theme::list(config.themes_dir.as_deref())? {
+        let marker = if theme.name() == config.theme {
+            "*"
+        } else {
+            " "
+        };
+        println!("{} {:<16} {}", marker, theme.name(), theme.description());
+    }
+    Ok(())
+}
+
+/// Describe a theme, or the site's theme if no name is given.
+pub fn show_theme(config: &Config, name: Option<&str>) -> Result<()> {
+    let theme = Theme::find(name.unwrap_or(&config.theme), config.themes_dir.as_deref())?;
+    println!("Name:        {}", theme.name());
+    println!("Description: {}", theme.description());
+    println!("Location:    {}", theme.location());
+    println!("Stylesheets:");
+    for stylesheet in theme.stylesheets() {
+        println!("  {}", stylesheet);
+    }
+    println!("Templates:");
+    for (template, _) in theme.templates()? {
+        println!("  {}", template);
+    }
+    println!("Static files:");
+    for file in theme.static_files()? {
+        println!("  {}", file.display());
+    }
+    if !theme.config().is_empty() {
+        println!("Default config:");
+        let config = toml::to_string(theme.config())
+            .map_err(|err| theme::Error::Serialize(theme.name().to_string(), err))?;
+        print!("{}", config);
+    }
+    Ok(())
+}
+
+/// Print statistics about all the posts.
+pub fn stats(config: &Config) -> Result<()> {
+    let posts = open_posts(config);
+    let stats = builder::stats(config, &site_theme(config)?, &posts)?;
+    print!("{}", stats);
+    Ok(())
+}
+
+fn site_theme(config: &Config) -> std::result::Result<Theme, theme::Error> {
+    Theme::find(&config.theme, config.themes_dir.as_deref())
+}
+
+fn open_posts(config: &Config) -> Posts {
+    Posts::new(
+        config.posts_dir.clone(),
+        config.timezone,
+        config.filename_patterns.clone(),
+    )
 }
 
-/// Edit the blog post with the given name inside the posts directory.
+/// Edit the blog post with the given name inside the posts directory,
+/// starting it with `skeleton` if it doesn't exist yet.
 ///
-/// If it changes, ensure the change is committed.
-fn edit_and_commit_post(posts: &Posts, name: &str) -> io::Result<()> {
+/// If it changes, ensure the change is committed. A new post counts as a
+/// change, even if it's just the skeleton.
+fn edit_and_commit_post(posts: &Posts, name: &str, skeleton: Option<String>) -> Result<()> {
     let post_file = posts.get_post_filename(name);
-    let changed = edit_file(&post_file)?;
+    let changed = edit_file(&post_file, skeleton)?;
     if changed {
-        posts.commit_post(&post_file, name)
+        posts
+            .commit_post(&post_file, name)
+            .map_err(|e| Error::Io(post_file, e))
     } else {
         Ok(())
     }
 }
 
-fn edit_file(filename: &Path) -> io::Result<bool> {
-    let prev = contents(filename);
-    edit(filename)?;
-    let current = contents(filename);
+fn edit_file(filename: &Path, skeleton: Option<String>) -> Result<bool> {
+    let prev = contents(filename)?;
+    if let (None, Some(skeleton)) = (&prev, skeleton) {
+        fs::write(filename, skeleton).map_err(|e| Error::Io(filename.to_owned(), e))?;
+    }
+    edit(filename).map_err(|e| Error::Io(filename.to_owned(), e))?;
+    let current = contents(filename)?;
     Ok(prev != current)
 }
 
 /// Get the contents of a file as a vector.
 ///
-/// If the file doesn't exist, return None. Panic if we get any other kind of
-/// error.
-fn contents(path: &Path) -> Option<Vec<u8>> {
-    match fs::read(&path) {
-        Ok(bytes) => Some(bytes),
+/// If the file doesn't exist, return None.
+fn contents(path: &Path) -> Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(bytes)),
         Err(err) => match err.kind() {
-            io::ErrorKind::NotFound => None,
-            _ => panic!("Could not read file: {}: {}", path.display(), err),
+            io::ErrorKind::NotFound => Ok(None),
+            _ => Err(Error::Io(path.to_owned(), err)),
         },
     }
 }
@@ -94,3 +260,85 @@ fn edit(file: &Path) -> io::Result<()> {
         .status()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use testing::TempDir;
+
+    #[test]
+    fn test_new_post_name() {
+        let now = Utc.ymd(2019, 11, 25).and_hms(10, 30, 0);
+        let name = |patterns: &[FilenamePattern], slug| new_post_name(patterns, &now, slug);
+        let all = [
+            FilenamePattern::DateTime,
+            FilenamePattern::Date,
+            FilenamePattern::DateSlug,
+        ];
+        let front_matter = [FilenamePattern::FrontMatter];
+
+        assert_eq!(
+            name(&all, None),
+            Some((String::from("2019-11-25-1030"), String::new()))
+        );
+        assert_eq!(
+            name(&all, Some("hello")),
+            Some((String::from("2019-11-25-hello"), String::new()))
+        );
+        assert_eq!(
+            name(&[FilenamePattern::Date], Some("hello")),
+            Some((String::from("2019-11-25"), String::new()))
+        );
+        assert_eq!(
+            name(&front_matter, Some("hello")),
+            Some((
+                String::from("hello"),
+                String::from("+++\ndate = 2019-11-25T10:30:00+00:00\n+++\n\n")
+            ))
+        );
+        assert_eq!(name(&front_matter, None), None);
+    }
+
+    #[test]
+    fn test_build_fingerprinted_image() {
+        let site = TempDir::new("fingerprinted-site");
+        let root = site.path();
+        fs::create_dir_all(root.join("posts")).unwrap();
+        fs::create_dir_all(root.join("static")).unwrap();
+        image::RgbImage::new(1000, 500)
+            .save(root.join("static/plot.png"))
+            .unwrap();
+        fs::write(
+            root.join("posts/2019-11-25-plot.md"),
+            "# Plot\n\n![A plot](/static/plot.png) and [the plot](/static/plot.png).\n",
+        )
+        .unwrap();
+        let mut config = Config {
+            posts_dir: root.join("posts"),
+            pages_dir: root.join("pages"),
+            static_dir: root.join("static"),
+            output_dir: root.join("site"),
+            ..Config::default()
+        };
+        config.assets.fingerprint = true;
+        config.images.widths = vec![480];
+
+        build(&config).unwrap();
+        let output = root.join("site");
+        let post = fs::read_to_string(output.join("posts/2019-11-25-plot.html")).unwrap();
+        let names: BTreeMap<String, String> =
+            serde_json::from_slice(&fs::read(output.join("static/assets.json")).unwrap()).unwrap();
+        let hashed = format!("/static/{}", names["plot.png"]);
+        let variant = format!("/_images{}", hashed.replace(".png", "-480w.png"));
+
+        assert!(output.join(&hashed[1..]).is_file());
+        assert!(!output.join("static/plot.png").exists());
+        assert!(post.contains(&format!("src=\"{}\"", hashed)));
+        assert!(post.contains(&format!("{} 1000w\"", hashed)));
+        assert!(post.contains(&format!("href=\"{}\"", hashed)));
+        assert!(post.contains(&format!("{} 480w", variant)));
+        assert!(output.join(&variant[1..]).is_file());
+        assert!(!post.contains("/static/plot.png"));
+    }
+}
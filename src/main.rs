@@ -1,15 +1,65 @@
 use clap::{App, Arg, SubCommand};
-use std::error::Error;
+use std::io::Write;
+use std::path::Path;
 use std::process;
 
-use blake;
+use blake::config::{self, Config};
 
-fn main() -> Result<(), Box<dyn Error>> {
+/// The environment variable that overrides `-v` and `-q`, using
+/// `env_logger`'s syntax, e.g. `BLAKE_LOG=debug` or `BLAKE_LOG=blake=trace`.
+const LOG_ENV_VAR: &str = "BLAKE_LOG";
+
+fn main() {
+    if let Err(error) = run() {
+        eprintln!("Error: {}", error);
+        process::exit(1);
+    }
+}
+
+fn run() -> blake::Result<()> {
     let app = App::new("blake")
         .version("0.1.0")
         .author("Jonathan M. Lange <jml@mumak.net>")
         .about("Situated blogging platform")
-        .subcommand(SubCommand::with_name("new"))
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .takes_value(true)
+                .default_value(config::CONFIG_FILE)
+                .help("Path to the site's configuration file"),
+        )
+        .arg(
+            Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .multiple(true)
+                .global(true)
+                .help("Say more about what's happening. Repeat for even more"),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .short("q")
+                .long("quiet")
+                .multiple(true)
+                .global(true)
+                .conflicts_with("verbose")
+                .help("Only report warnings. Repeat to only report errors"),
+        )
+        .subcommand(
+            SubCommand::with_name("new")
+                .arg(
+                    Arg::with_name("title")
+                        .long("title")
+                        .takes_value(true)
+                        .help("Title of the new post"),
+                )
+                .arg(
+                    Arg::with_name("slug")
+                        .long("slug")
+                        .takes_value(true)
+                        .help("Name for the post's file. Defaults to one made from the title."),
+                ),
+        )
         .subcommand(SubCommand::with_name("edit"))
         .subcommand(
             SubCommand::with_name("build")
@@ -19,17 +69,104 @@ fn main() -> Result<(), Box<dyn Error>> {
                 .arg(
                     Arg::with_name("--posts-only")
                         .help("Only build posts, don't build the indexes."),
+                )
+                .arg(
+                    Arg::with_name("relative-urls")
+                        .long("relative-urls")
+                        .help("Make links relative, so the site can be browsed from disk"),
+                )
+                .arg(
+                    Arg::with_name("keep-going")
+                        .long("keep-going")
+                        .conflicts_with("fail-fast")
+                        .help("Report every post that fails to build (the default)"),
+                )
+                .arg(
+                    Arg::with_name("fail-fast")
+                        .long("fail-fast")
+                        .help("Stop at the first post that fails to build"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("theme")
+                .about("Show the themes a site can use")
+                .subcommand(SubCommand::with_name("list").about("List the available themes"))
+                .subcommand(
+                    SubCommand::with_name("show").about("Describe a theme").arg(
+                        Arg::with_name("name")
+                            .help("The theme to describe. Defaults to the site's theme."),
+                    ),
                 ),
-        );
+        )
+        .subcommand(
+            SubCommand::with_name("rollback")
+                .about("Put the previous build of the site back in place"),
+        )
+        .subcommand(SubCommand::with_name("stats").about("Show statistics about the posts"));
     let matches = app.get_matches();
-    match matches.subcommand_name() {
-        Some("new") => blake::new_post()?,
-        Some("edit") => blake::edit_post()?,
-        Some("build") => blake::build()?,
-        Some(_) | None => {
+    // Global flags can come before or after the subcommand, and either way
+    // end up in the subcommand's matches.
+    let flags = matches.subcommand().1.unwrap_or(&matches);
+    init_logging(
+        flags.occurrences_of("verbose"),
+        flags.occurrences_of("quiet"),
+    );
+    let config_path = Path::new(matches.value_of("config").unwrap_or(config::CONFIG_FILE));
+    let mut config = Config::load_or_default(config_path)?;
+    match matches.subcommand() {
+        ("new", Some(new_matches)) => blake::new_post(
+            &config,
+            new_matches.value_of("title"),
+            new_matches.value_of("slug"),
+        )?,
+        ("edit", _) => blake::edit_post(&config)?,
+        ("build", Some(build_matches)) => {
+            if build_matches.is_present("relative-urls") {
+                config.relative_urls = true;
+            }
+            if build_matches.is_present("fail-fast") {
+                config.fail_fast = true;
+            } else if build_matches.is_present("keep-going") {
+                config.fail_fast = false;
+            }
+            blake::build(&config)?;
+        }
+        ("theme", Some(theme_matches)) => match theme_matches.subcommand() {
+            ("show", Some(show_matches)) => {
+                blake::show_theme(&config, show_matches.value_of("name"))?
+            }
+            _ => blake::list_themes(&config)?,
+        },
+        ("rollback", _) => blake::rollback(&config)?,
+        ("stats", _) => blake::stats(&config)?,
+        _ => {
             println!("Invalid subcommand given.");
             process::exit(2);
         }
     }
     Ok(())
 }
+
+/// Log to stderr: progress by default, more detail with each `-v`, and only
+/// warnings and then errors with each `-q`, unless the environment says
+/// otherwise.
+fn init_logging(verbose: u64, quiet: u64) {
+    let level = match (verbose, quiet) {
+        (0, 0) => log::LevelFilter::Info,
+        (0, 1) => log::LevelFilter::Warn,
+        (0, _) => log::LevelFilter::Error,
+        (1, _) => log::LevelFilter::Debug,
+        (_, _) => log::LevelFilter::Trace,
+    };
+    let mut builder = env_logger::Builder::new();
+    match std::env::var(LOG_ENV_VAR) {
+        Ok(filters) => builder.parse_filters(&filters),
+        Err(_) => builder.filter_module("blake", level),
+    };
+    builder
+        .format(|buf, record| match record.level() {
+            log::Level::Info => writeln!(buf, "{}", record.args()),
+            level => writeln!(buf, "{}: {}", level, record.args()),
+        })
+        .init();
+}
@@ -0,0 +1,82 @@
+use crate::walk;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The source file of a directory's page, e.g. `now/index.md` for `/now/`.
+const INDEX_PAGE: &str = "index";
+
+/// Standalone pages, like About or Now, that aren't dated posts.
+///
+/// Pages can be in subdirectories, and are published at the same place in
+/// the site as they are in the pages directory, so `about.md` becomes
+/// `/about.html`, and `now/index.md` becomes `/now/`.
+#[derive(Debug)]
+pub struct Pages {
+    path: PathBuf,
+}
+
+impl Pages {
+    pub fn new(path: PathBuf) -> Pages {
+        Pages { path }
+    }
+
+    /// All the pages, in order of their paths. If there's no pages directory,
+    /// there are no pages.
+    pub fn pages(&self) -> io::Result<Vec<Page>> {
+        if !self.path.is_dir() {
+            return Ok(Vec::new());
+        }
+        let files = walk::files(&self.path, |_| false)?;
+        Ok(files
+            .into_iter()
+            .filter(|relative| relative.extension().is_some_and(|ext| ext == "md"))
+            .map(|relative| Page {
+                url: page_url(&relative),
+                path: self.path.join(relative),
+            })
+            .collect())
+    }
+}
+
+/// The URL of the page at `relative`, a path within the pages directory.
+fn page_url(relative: &Path) -> String {
+    let mut url = String::new();
+    for component in relative.with_extension("").components() {
+        url.push('/');
+        url.push_str(&component.as_os_str().to_string_lossy());
+    }
+    match url.strip_suffix(INDEX_PAGE) {
+        Some(dir) if dir.ends_with('/') => dir.to_owned(),
+        _ => url + ".html",
+    }
+}
+
+#[derive(Debug)]
+pub struct Page {
+    path: PathBuf,
+    url: String,
+}
+
+impl Page {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_url() {
+        assert_eq!(page_url(Path::new("about.md")), "/about.html");
+        assert_eq!(page_url(Path::new("index.md")), "/");
+        assert_eq!(page_url(Path::new("now/index.md")), "/now/");
+        assert_eq!(page_url(Path::new("now/reading.md")), "/now/reading.html");
+        assert_eq!(page_url(Path::new("reindex.md")), "/reindex.html");
+    }
+}
@@ -1,30 +1,61 @@
 use chrono::prelude::*;
+use chrono_tz::Tz;
 use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
 use std::process;
 
+use crate::config::FilenamePattern;
+use crate::front_matter;
+use crate::walk;
+
+/// The date part of post filenames.
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// The Markdown file of a post that's a directory, with its own files.
+const BUNDLE_INDEX: &str = "index.md";
+
 #[derive(Debug)]
 pub struct Posts {
     path: PathBuf,
+    timezone: Tz,
+    patterns: Vec<FilenamePattern>,
 }
 
 impl Posts {
-    pub fn new(path: PathBuf) -> Posts {
-        Posts { path }
+    /// Posts in `path`, dated in `timezone`, with filenames that match one of
+    /// `patterns`.
+    pub fn new(path: PathBuf, timezone: Tz, patterns: Vec<FilenamePattern>) -> Posts {
+        Posts {
+            path,
+            timezone,
+            patterns,
+        }
     }
 
+    /// The path of the newest post, by date, if there are any posts.
+    ///
+    /// Posts whose dates can't be worked out are left out.
     pub fn get_latest_file(&self) -> io::Result<Option<PathBuf>> {
-        let entries = fs::read_dir(&self.path)?;
-        let mut paths = vec![];
-        for entry in entries {
-            let entry = entry?;
-            paths.push(entry.path())
+        let mut latest: Option<(DateTime<Tz>, PathBuf)> = None;
+        for path in self.post_paths()? {
+            match self.post(path.clone()) {
+                Ok(post) => {
+                    if latest.as_ref().is_none_or(|(date, _)| post.date() >= date) {
+                        latest = Some((*post.date(), path));
+                    }
+                }
+                Err(e) => log::warn!("Skipping {}: {}", path.display(), e),
+            }
         }
-        Ok(paths.into_iter().max())
+        Ok(latest.map(|(_, path)| path))
     }
 
     pub fn get_post_filename(&self, name: &str) -> PathBuf {
+        let bundle_index = self.path.join(name).join(BUNDLE_INDEX);
+        if bundle_index.is_file() {
+            return bundle_index;
+        }
         let mut post_file = self.path.to_owned();
         post_file.push(name);
         post_file.set_extension("md");
@@ -46,31 +77,25 @@ impl Posts {
         Ok(())
     }
 
-    /// Is there a post with the given name?
-    pub fn is_post<T: AsRef<Path>>(&self, name: T) -> bool {
-        let source_path = self.path.join(&name).with_extension("md");
-        source_path.is_file()
-    }
-
-    pub fn iter_posts(&self) -> io::Result<impl Iterator<Item = Result<Post, Error>>> {
-        self.path.read_dir().map(|entries| {
-            entries
-                .map(|entry| entry.map(|e| e.path()))
-                .filter_map(|entry| match entry {
-                    Err(e) => Some(Err(e)),
-                    Ok(path) => {
-                        if has_extension(&path, "md") {
-                            Some(Ok(path))
-                        } else {
-                            None
-                        }
-                    }
-                })
-                .map(|entry| {
-                    let path = entry?;
-                    Post::new(path)
-                })
-        })
+    /// The paths of all the posts, in order: Markdown files, and
+    /// directories with an `index.md`.
+    pub fn post_paths(&self) -> io::Result<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        for entry in self.path.read_dir()? {
+            let path = entry?.path();
+            if has_extension(&path, "md") || path.join(BUNDLE_INDEX).is_file() {
+                paths.push(path);
+            } else {
+                log::trace!("Skipping {}, which isn't a post", path.display());
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// The post at `path`, one of the [`post_paths`](Posts::post_paths).
+    pub fn post(&self, path: PathBuf) -> Result<Post, Error> {
+        Post::new(path, self.timezone, &self.patterns)
     }
 }
 
@@ -83,11 +108,16 @@ fn has_extension<T: AsRef<std::ffi::OsStr>>(path: &Path, extension: T) -> bool {
 
 /// Errors that can be caused by creating a Post.
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     NoFileName(PathBuf),
     BadFileName(PathBuf),
+    UnrecognisedFileName(PathBuf),
     IoError(io::Error),
     InvalidDateError(chrono::ParseError),
+    /// The post's local time is skipped when the clocks go forward.
+    NonexistentTime(NaiveDateTime, Tz),
+    InvalidFrontMatter(front_matter::Error),
 }
 
 impl std::fmt::Display for Error {
@@ -95,19 +125,43 @@ impl std::fmt::Display for Error {
         match self {
             Error::NoFileName(path) => write!(f, "No such filename: {}", path.display()),
             Error::BadFileName(path) => write!(f, "Cannot decode filename: {}", path.display()),
+            Error::UnrecognisedFileName(path) => write!(
+                f,
+                "Filename does not match any configured pattern: {}",
+                path.display()
+            ),
             Error::IoError(io_error) => write!(f, "Cannot read file: {}", io_error),
             Error::InvalidDateError(parse_error) => {
-                write!(f, "Filename is not a valid date: {}", parse_error)
+                write!(f, "Post does not have a valid date: {}", parse_error)
+            }
+            Error::NonexistentTime(time, timezone) => write!(
+                f,
+                "Post's time does not exist in {}, because the clocks change: {}",
+                timezone, time
+            ),
+            Error::InvalidFrontMatter(toml_error) => {
+                write!(f, "Cannot parse front matter: {}", toml_error)
             }
         }
     }
 }
 
+impl Error {
+    /// The line of the post the error is on, counting from 1, if known.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Error::InvalidFrontMatter(error) => error.line(),
+            _ => None,
+        }
+    }
+}
+
 impl std::error::Error for Error {
     fn cause(&self) -> Option<&dyn std::error::Error> {
         match self {
             Error::IoError(io_error) => Some(io_error),
             Error::InvalidDateError(parse_error) => Some(parse_error),
+            Error::InvalidFrontMatter(toml_error) => Some(toml_error),
             _ => None,
         }
     }
@@ -119,6 +173,12 @@ impl From<io::Error> for Error {
     }
 }
 
+impl From<front_matter::Error> for Error {
+    fn from(error: front_matter::Error) -> Self {
+        Error::InvalidFrontMatter(error)
+    }
+}
+
 impl From<chrono::ParseError> for Error {
     fn from(error: chrono::ParseError) -> Self {
         Error::InvalidDateError(error)
@@ -127,21 +187,47 @@ impl From<chrono::ParseError> for Error {
 
 pub struct Post {
     path: PathBuf,
+    /// The directory of a post that has its own files.
+    bundle: Option<PathBuf>,
     name: String,
-    date: DateTime<Utc>,
+    slug: String,
+    date: DateTime<Tz>,
 }
 
 impl Post {
-    pub fn new(path: PathBuf) -> Result<Post, Error> {
-        let name = path
-            .file_stem()
-            .ok_or_else(|| Error::NoFileName(path.clone()))?;
+    /// The post at `path`, which is either a Markdown file, or a directory
+    /// with an `index.md` and any files the post needs, like images.
+    pub fn new(path: PathBuf, timezone: Tz, patterns: &[FilenamePattern]) -> Result<Post, Error> {
+        let (path, bundle) = if path.is_dir() {
+            (path.join(BUNDLE_INDEX), Some(path))
+        } else {
+            (path, None)
+        };
+        let name = match &bundle {
+            Some(bundle) => bundle.file_name(),
+            None => path.file_stem(),
+        };
+        let name = name.ok_or_else(|| Error::NoFileName(path.clone()))?;
         let name = name
             .to_str()
             .ok_or_else(|| Error::BadFileName(path.clone()))?;
         let name = String::from(name);
-        let date = Utc.datetime_from_str(&name, crate::POST_DATE_FORMAT)?;
-        Ok(Post { path, name, date })
+        for pattern in patterns {
+            if let Some(date) = match_pattern(*pattern, &path, &name, timezone)? {
+                let slug = match split_date_slug(&name) {
+                    Some((_, slug)) if *pattern == FilenamePattern::DateSlug => slug.to_owned(),
+                    _ => slugify(&name),
+                };
+                return Ok(Post {
+                    path,
+                    bundle,
+                    name,
+                    slug,
+                    date,
+                });
+            }
+        }
+        Err(Error::UnrecognisedFileName(path))
     }
 
     pub fn path(&self) -> &Path {
@@ -152,7 +238,264 @@ impl Post {
         &self.name
     }
 
-    pub fn date(&self) -> &DateTime<Utc> {
+    /// The post's own files, relative to its directory. Posts that are just
+    /// a Markdown file have none.
+    pub fn bundle_files(&self) -> io::Result<Vec<PathBuf>> {
+        match &self.bundle {
+            Some(bundle) => walk::files(bundle, |file| file == Path::new(BUNDLE_INDEX)),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// The directory of a post that has its own files.
+    pub fn bundle(&self) -> Option<&Path> {
+        self.bundle.as_deref()
+    }
+
+    /// A version of the post's name that's suitable for URLs.
+    pub fn slug(&self) -> &str {
+        &self.slug
+    }
+
+    pub fn date(&self) -> &DateTime<Tz> {
         &self.date
     }
 }
+
+/// Try to get a post's date from its name using a single pattern.
+///
+/// Returns `None` if the pattern doesn't match.
+fn match_pattern(
+    pattern: FilenamePattern,
+    path: &Path,
+    name: &str,
+    timezone: Tz,
+) -> Result<Option<DateTime<Tz>>, Error> {
+    match pattern {
+        FilenamePattern::DateTime => {
+            let time = [crate::POST_DATE_FORMAT, crate::OLD_POST_DATE_FORMAT]
+                .iter()
+                .find_map(|format| NaiveDateTime::parse_from_str(name, format).ok());
+            match time {
+                Some(time) => Ok(Some(local_time(time, timezone)?)),
+                None => Ok(None),
+            }
+        }
+        FilenamePattern::Date => parse_date(name, timezone),
+        FilenamePattern::DateSlug => match split_date_slug(name) {
+            Some((date, _)) => parse_date(date, timezone),
+            None => Ok(None),
+        },
+        FilenamePattern::FrontMatter => {
+            let contents = fs::read_to_string(path)?;
+            let (front_matter, _) = front_matter::split(&contents)?;
+            match front_matter.date {
+                None => Ok(None),
+                Some(date) => Ok(Some(parse_front_matter_date(&date.to_string(), timezone)?)),
+            }
+        }
+    }
+}
+
+/// Split a name like `2019-11-25-my-title` into its date and slug.
+fn split_date_slug(name: &str) -> Option<(&str, &str)> {
+    let date_len = "YYYY-MM-DD".len();
+    let date = name.get(..date_len)?;
+    let slug = name.get(date_len..)?.strip_prefix('-')?;
+    if slug.is_empty() {
+        None
+    } else {
+        Some((date, slug))
+    }
+}
+
+/// Parse a date without a time, taking it to mean midnight.
+///
+/// Returns `None` if it isn't a date.
+fn parse_date(date: &str, timezone: Tz) -> Result<Option<DateTime<Tz>>, Error> {
+    match NaiveDate::parse_from_str(date, DATE_FORMAT) {
+        Ok(date) => Ok(Some(local_time(date.and_hms(0, 0, 0), timezone)?)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// The moment `time` on the clocks in `timezone`.
+///
+/// When the clocks go back, and `time` happens twice, it's the first time.
+/// When they go forward, and it doesn't happen at all, it's an error.
+fn local_time(time: NaiveDateTime, timezone: Tz) -> Result<DateTime<Tz>, Error> {
+    timezone
+        .from_local_datetime(&time)
+        .earliest()
+        .ok_or(Error::NonexistentTime(time, timezone))
+}
+
+/// Parse a date from front matter.
+///
+/// The date can be an RFC 3339 timestamp, a local date and time, or just a
+/// date. Anything without an offset is in the site's timezone.
+fn parse_front_matter_date(date: &str, timezone: Tz) -> Result<DateTime<Tz>, Error> {
+    if let Some(date) = parse_date(date, timezone)? {
+        return Ok(date);
+    }
+    match DateTime::parse_from_rfc3339(date) {
+        Ok(date) => Ok(date.with_timezone(&timezone)),
+        Err(_) => local_time(
+            NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S")?,
+            timezone,
+        ),
+    }
+}
+
+/// Turn arbitrary text, such as a post title, into something suitable for a
+/// filename or URL.
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::with_capacity(text.len());
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_latest_file() {
+        let dir = TempDir::new("latest-post");
+        let write =
+            |name: &str, contents: &str| fs::write(dir.path().join(name), contents).unwrap();
+        write("2019-11-25-hello.md", "# Hello\n");
+        write("2019-12-01-1030.md", "# Later\n");
+        write("about-rust.md", "+++\ndate = 2019-01-01\n+++\n");
+        write("undated.md", "# No date\n");
+        write("notes.txt", "");
+        let patterns = vec![
+            FilenamePattern::DateTime,
+            FilenamePattern::DateSlug,
+            FilenamePattern::FrontMatter,
+        ];
+
+        let latest = Posts::new(dir.path().to_owned(), Tz::UTC, patterns)
+            .get_latest_file()
+            .unwrap();
+        let empty = TempDir::new("no-posts");
+        let none = Posts::new(empty.path().to_owned(), Tz::UTC, Vec::new())
+            .get_latest_file()
+            .unwrap();
+
+        assert_eq!(latest, Some(dir.path().join("2019-12-01-1030.md")));
+        assert_eq!(none, None);
+    }
+
+    #[test]
+    fn test_date_in_timezone() {
+        let path = PathBuf::from("posts/2019-11-25-21:30.md");
+        let post = Post::new(path, Tz::America__New_York, &[FilenamePattern::DateTime]).unwrap();
+        assert_eq!(post.name(), "2019-11-25-21:30");
+        assert_eq!(post.slug(), "2019-11-25-21-30");
+        assert_eq!(post.date().to_rfc3339(), "2019-11-25T21:30:00-05:00");
+        assert_eq!(
+            post.date().with_timezone(&Utc).to_rfc3339(),
+            "2019-11-26T02:30:00+00:00"
+        );
+    }
+
+    #[test]
+    fn test_date_time_without_colon() {
+        let path = PathBuf::from("posts/2019-11-25-2130.md");
+        let post = Post::new(path, Tz::UTC, &[FilenamePattern::DateTime]).unwrap();
+        assert_eq!(post.slug(), "2019-11-25-2130");
+        assert_eq!(post.date().to_rfc3339(), "2019-11-25T21:30:00+00:00");
+    }
+
+    #[test]
+    fn test_date_when_clocks_change() {
+        let timezone = Tz::Europe__London;
+        let new = |name: &str| {
+            let path = PathBuf::from(format!("posts/{}.md", name));
+            Post::new(path, timezone, &[FilenamePattern::DateTime])
+        };
+        let twice = new("2019-10-27-01:30").unwrap();
+        let skipped = new("2019-03-31-01:30").err().unwrap();
+
+        assert_eq!(twice.date().to_rfc3339(), "2019-10-27T01:30:00+01:00");
+        assert!(matches!(skipped, Error::NonexistentTime(..)));
+        assert_eq!(
+            skipped.to_string(),
+            "Post's time does not exist in Europe/London, because the clocks change: \
+             2019-03-31 01:30:00"
+        );
+        assert_eq!(
+            parse_front_matter_date("2019-10-27T01:30:00", timezone)
+                .unwrap()
+                .to_rfc3339(),
+            "2019-10-27T01:30:00+01:00"
+        );
+    }
+
+    #[test]
+    fn test_invalid_date() {
+        let path = PathBuf::from("posts/not-a-date.md");
+        assert!(Post::new(path, Tz::UTC, &[FilenamePattern::DateTime]).is_err());
+    }
+
+    #[test]
+    fn test_date_only() {
+        let path = PathBuf::from("posts/2019-11-25.md");
+        let post = Post::new(
+            path,
+            Tz::UTC,
+            &[FilenamePattern::DateTime, FilenamePattern::Date],
+        )
+        .unwrap();
+        assert_eq!(post.date().to_rfc3339(), "2019-11-25T00:00:00+00:00");
+    }
+
+    #[test]
+    fn test_date_slug() {
+        let path = PathBuf::from("posts/2019-11-25-my-title.md");
+        let post = Post::new(
+            path,
+            Tz::UTC,
+            &[FilenamePattern::Date, FilenamePattern::DateSlug],
+        )
+        .unwrap();
+        assert_eq!(post.name(), "2019-11-25-my-title");
+        assert_eq!(post.slug(), "my-title");
+        assert_eq!(post.date().to_rfc3339(), "2019-11-25T00:00:00+00:00");
+    }
+
+    #[test]
+    fn test_pattern_not_allowed() {
+        let path = PathBuf::from("posts/2019-11-25-my-title.md");
+        assert!(Post::new(path, Tz::UTC, &[FilenamePattern::DateTime]).is_err());
+    }
+
+    #[test]
+    fn test_front_matter_dates() {
+        let timezone = Tz::Europe__London;
+        let parse = |date| {
+            parse_front_matter_date(date, timezone)
+                .unwrap()
+                .to_rfc3339()
+        };
+        assert_eq!(parse("2019-11-25"), "2019-11-25T00:00:00+00:00");
+        assert_eq!(parse("2019-07-25T10:30:00"), "2019-07-25T10:30:00+01:00");
+        assert_eq!(parse("2019-07-25T10:30:00Z"), "2019-07-25T11:30:00+01:00");
+        assert!(parse_front_matter_date("yesterday", timezone).is_err());
+    }
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("My Title"), "my-title");
+        assert_eq!(slugify("  What's `new`?  "), "what-s-new");
+        assert_eq!(slugify("Café au lait"), "café-au-lait");
+    }
+}
@@ -0,0 +1,350 @@
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::{fmt, fs, io};
+
+use crate::walk;
+
+/// The theme sites use unless they say otherwise.
+pub const DEFAULT_THEME: &str = "tufte";
+
+/// The description file at the top of every theme.
+const THEME_FILE: &str = "theme.toml";
+
+/// A theme that's compiled into blake.
+#[derive(Debug)]
+struct Builtin {
+    name: &'static str,
+    theme_file: &'static str,
+    templates: &'static [(&'static str, &'static str)],
+    static_files: &'static [(&'static str, &'static [u8])],
+}
+
+const BUILTIN_THEMES: &[Builtin] = &[Builtin {
+    name: "tufte",
+    theme_file: include_str!("../themes/tufte/theme.toml"),
+    templates: &[
+        (
+            "base.html",
+            include_str!("../themes/tufte/templates/base.html"),
+        ),
+        (
+            "feed.xml",
+            include_str!("../themes/tufte/templates/feed.xml"),
+        ),
+        (
+            "index.html",
+            include_str!("../themes/tufte/templates/index.html"),
+        ),
+        (
+            "page.html",
+            include_str!("../themes/tufte/templates/page.html"),
+        ),
+        (
+            "post.html",
+            include_str!("../themes/tufte/templates/post.html"),
+        ),
+        (
+            "redirect.html",
+            include_str!("../themes/tufte/templates/redirect.html"),
+        ),
+    ],
+    static_files: &[(
+        "tufte.css",
+        include_bytes!("../themes/tufte/static/tufte.css"),
+    )],
+}];
+
+/// A bundle of templates, static assets and default configuration that
+/// gives a site its look.
+///
+/// A theme is a directory with a `theme.toml`, a `templates` directory and a
+/// `static` directory. The site's own templates and static files take the
+/// place of the theme's ones with the same name.
+#[derive(Clone, Debug)]
+pub struct Theme {
+    name: String,
+    info: ThemeInfo,
+    source: Source,
+}
+
+/// What a theme says about itself in its `theme.toml`.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ThemeInfo {
+    description: String,
+    /// Stylesheets every page links to, as paths within the site.
+    stylesheets: Vec<String>,
+    /// Defaults for the site's configuration.
+    config: toml::value::Table,
+}
+
+#[derive(Clone, Debug)]
+enum Source {
+    Builtin(&'static Builtin),
+    Dir(PathBuf),
+}
+
+impl Theme {
+    /// Find the theme called `name`.
+    ///
+    /// Themes in `themes_dir` come before the built-in themes.
+    pub fn find(name: &str, themes_dir: Option<&Path>) -> Result<Theme, Error> {
+        if let Some(themes_dir) = themes_dir {
+            let path = themes_dir.join(name);
+            if path.is_dir() {
+                return Theme::load(name, path);
+            }
+        }
+        match BUILTIN_THEMES.iter().find(|builtin| builtin.name == name) {
+            Some(builtin) => Theme::builtin(builtin),
+            None => Err(Error::NotFound(name.to_owned())),
+        }
+    }
+
+    /// All the themes available to a site, built-in themes first.
+    ///
+    /// Themes in `themes_dir` with the same name as a built-in theme are
+    /// listed as well, since they're the ones that will be used.
+    pub fn list(themes_dir: Option<&Path>) -> Result<Vec<Theme>, Error> {
+        let mut themes = BUILTIN_THEMES
+            .iter()
+            .map(Theme::builtin)
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some(themes_dir) = themes_dir {
+            for path in list_dir(themes_dir)? {
+                if !path.is_dir() {
+                    continue;
+                }
+                if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+                    let name = name.to_owned();
+                    themes.push(Theme::load(&name, path)?);
+                }
+            }
+        }
+        Ok(themes)
+    }
+
+    fn builtin(builtin: &'static Builtin) -> Result<Theme, Error> {
+        let info = toml::from_str(builtin.theme_file)
+            .map_err(|e| Error::Parse(PathBuf::from(builtin.name).join(THEME_FILE), e))?;
+        Ok(Theme {
+            name: builtin.name.to_owned(),
+            info,
+            source: Source::Builtin(builtin),
+        })
+    }
+
+    fn load(name: &str, path: PathBuf) -> Result<Theme, Error> {
+        let theme_file = path.join(THEME_FILE);
+        let info = match fs::read_to_string(&theme_file) {
+            Ok(contents) => {
+                toml::from_str(&contents).map_err(|e| Error::Parse(theme_file.clone(), e))?
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => ThemeInfo::default(),
+            Err(e) => return Err(Error::Io(theme_file, e)),
+        };
+        Ok(Theme {
+            name: name.to_owned(),
+            info,
+            source: Source::Dir(path),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn description(&self) -> &str {
+        &self.info.description
+    }
+
+    /// Where the theme comes from, for showing to people.
+    pub fn location(&self) -> String {
+        match &self.source {
+            Source::Builtin(_) => String::from("built in"),
+            Source::Dir(path) => path.display().to_string(),
+        }
+    }
+
+    pub fn stylesheets(&self) -> &[String] {
+        &self.info.stylesheets
+    }
+
+    /// The theme's defaults for the site's configuration.
+    pub fn config(&self) -> &toml::value::Table {
+        &self.info.config
+    }
+
+    /// The names and contents of the theme's templates.
+    pub fn templates(&self) -> Result<Vec<(String, String)>, Error> {
+        match &self.source {
+            Source::Builtin(builtin) => Ok(builtin
+                .templates
+                .iter()
+                .map(|(name, contents)| (String::from(*name), String::from(*contents)))
+                .collect()),
+            Source::Dir(path) => read_templates(&path.join("templates")),
+        }
+    }
+
+    /// The paths of the theme's static files, relative to its static directory.
+    pub fn static_files(&self) -> Result<Vec<PathBuf>, Error> {
+        match &self.source {
+            Source::Builtin(builtin) => Ok(builtin
+                .static_files
+                .iter()
+                .map(|(name, _)| PathBuf::from(name))
+                .collect()),
+            Source::Dir(path) => {
+                let static_dir = path.join("static");
+                if !static_dir.is_dir() {
+                    return Ok(Vec::new());
+                }
+                walk::files(&static_dir, |_| false).map_err(|e| Error::Io(static_dir.clone(), e))
+            }
+        }
+    }
+
+    /// Where to find the contents of the theme's static file `name`, one of
+    /// its [`static_files`](Theme::static_files).
+    pub fn static_file(&self, name: &Path) -> StaticFile {
+        match &self.source {
+            Source::Builtin(builtin) => {
+                let (_, contents) = builtin
+                    .static_files
+                    .iter()
+                    .find(|(file, _)| Path::new(file) == name)
+                    .expect("Built-in static files are listed from the same table.");
+                StaticFile::Builtin(contents)
+            }
+            Source::Dir(path) => StaticFile::Path(path.join("static").join(name)),
+        }
+    }
+}
+
+/// Where the contents of one of a theme's static files are.
+#[derive(Clone, Debug)]
+pub enum StaticFile {
+    Builtin(&'static [u8]),
+    Path(PathBuf),
+}
+
+/// Read all the templates in `templates_dir`, named after their files.
+///
+/// A missing directory has no templates.
+pub fn read_templates(templates_dir: &Path) -> Result<Vec<(String, String)>, Error> {
+    let mut templates = Vec::new();
+    if !templates_dir.exists() {
+        return Ok(templates);
+    }
+    for path in list_dir(templates_dir)? {
+        if !path.is_file() {
+            continue;
+        }
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| Error::BadFileName(path.clone()))?
+            .to_owned();
+        let contents = fs::read_to_string(&path).map_err(|e| Error::Io(path.clone(), e))?;
+        templates.push((name, contents));
+    }
+    Ok(templates)
+}
+
+/// The paths in `dir`, in order.
+fn list_dir(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let entries = fs::read_dir(dir).map_err(|e| Error::Io(dir.to_owned(), e))?;
+    let mut paths = entries
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()
+        .map_err(|e| Error::Io(dir.to_owned(), e))?;
+    paths.sort();
+    Ok(paths)
+}
+
+/// Errors that can be caused by finding or loading a theme.
+#[derive(Debug)]
+pub enum Error {
+    NotFound(String),
+    BadFileName(PathBuf),
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Serialize(String, toml::ser::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::NotFound(name) => write!(f, "No such theme: {}", name),
+            Error::BadFileName(path) => write!(f, "Cannot decode filename: {}", path.display()),
+            Error::Io(path, err) => write!(f, "Cannot read theme {}: {}", path.display(), err),
+            Error::Parse(path, err) => write!(f, "Invalid theme {}: {}", path.display(), err),
+            Error::Serialize(name, err) => {
+                write!(f, "Cannot write config of theme {}: {}", name, err)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(_, err) => Some(err),
+            Error::Parse(_, err) => Some(err),
+            Error::Serialize(_, err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_default_theme_is_builtin() {
+        let theme = Theme::find(DEFAULT_THEME, None).unwrap();
+        assert_eq!(theme.location(), "built in");
+        assert_eq!(theme.stylesheets(), ["/static/tufte.css"]);
+        let templates = theme.templates().unwrap();
+        assert!(templates.iter().any(|(name, _)| name == "post.html"));
+        assert_eq!(theme.static_files().unwrap(), [PathBuf::from("tufte.css")]);
+    }
+
+    #[test]
+    fn test_unknown_theme() {
+        assert!(Theme::find("no-such-theme", None).is_err());
+    }
+
+    #[test]
+    fn test_theme_dir() {
+        let themes_dir = TempDir::new("themes");
+        let themes_dir = themes_dir.path();
+        let theme_dir = themes_dir.join("plain");
+        fs::create_dir_all(theme_dir.join("static/css")).unwrap();
+        fs::write(
+            theme_dir.join(THEME_FILE),
+            "description = \"Plain\"\n[config]\npermalink = \"/{slug}/\"\n",
+        )
+        .unwrap();
+        fs::write(theme_dir.join("static/css/plain.css"), "").unwrap();
+        let theme = Theme::find("plain", Some(themes_dir)).unwrap();
+        let static_files = theme.static_files();
+        let names = Theme::list(Some(themes_dir)).map(|themes| {
+            themes
+                .iter()
+                .map(|theme| theme.name().to_owned())
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(theme.description(), "Plain");
+        assert_eq!(
+            theme.config().get("permalink"),
+            Some(&toml::Value::String(String::from("/{slug}/")))
+        );
+        assert_eq!(theme.templates().unwrap(), vec![]);
+        assert_eq!(static_files.unwrap(), [PathBuf::from("css/plain.css")]);
+        assert_eq!(names.unwrap(), vec!["tufte", "plain"]);
+    }
+}
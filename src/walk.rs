@@ -0,0 +1,60 @@
+//! Finding all the files in a directory tree.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The files under `root`, relative to it, in order.
+///
+/// Files and directories for which `skip` returns true, given their path
+/// relative to `root`, are left out, along with everything inside them.
+pub fn files<F>(root: &Path, skip: F) -> io::Result<Vec<PathBuf>>
+where
+    F: Fn(&Path) -> bool,
+{
+    let mut files = Vec::new();
+    add_files(root, Path::new(""), &skip, &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+/// Add the files under `root.join(dir)` to `files`.
+fn add_files<F>(root: &Path, dir: &Path, skip: &F, files: &mut Vec<PathBuf>) -> io::Result<()>
+where
+    F: Fn(&Path) -> bool,
+{
+    for entry in fs::read_dir(root.join(dir))? {
+        let path = entry?.path();
+        let relative = dir.join(path.file_name().expect("Directory entries have names."));
+        if skip(&relative) {
+            continue;
+        }
+        if path.is_dir() {
+            add_files(root, &relative, skip, files)?;
+        } else {
+            files.push(relative);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_files() {
+        let root = TempDir::new("walk");
+        let root = root.path();
+        fs::create_dir_all(root.join("b/c")).unwrap();
+        fs::create_dir_all(root.join("skipped")).unwrap();
+        fs::write(root.join("b/c/d.txt"), "").unwrap();
+        fs::write(root.join("a.txt"), "").unwrap();
+        fs::write(root.join("skipped/e.txt"), "").unwrap();
+
+        let files = files(root, |path| path.ends_with("skipped")).unwrap();
+        assert_eq!(files, [PathBuf::from("a.txt"), PathBuf::from("b/c/d.txt")]);
+        assert!(super::files(&root.join("missing"), |_| false).is_err());
+    }
+}